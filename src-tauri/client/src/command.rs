@@ -1,11 +1,18 @@
 use crate::settings::{self};
 use tokio::sync::mpsc::UnboundedSender;
+use websocket::message::PresenceStatus;
 
 #[derive(Debug)]
 pub enum Command {
     ListUsers,
     // Reconnect(Settings),
-    SendPrompt(String, String),
+    SendPrompt(Vec<String>, String),
+    SetPresence(PresenceStatus),
+    BlockUser(String),
+    UnblockUser(String),
+    MuteUser(String),
+    UnmuteUser(String),
+    Typing(String),
     // SaveSettings(String, String),
 }
 
@@ -21,8 +28,8 @@ impl CommandState {
 }
 
 #[tauri::command]
-pub async fn save_settings(username: String, server: String) -> Result<String, bool> {
-    let settings = settings::Settings::new(username, server);
+pub async fn save_settings(username: String, server: String, e2e: bool) -> Result<String, bool> {
+    let settings = settings::Settings::new(username, server, e2e);
     let res = settings.save_to_system_path();
 
     if let Ok(path) = res {
@@ -35,13 +42,25 @@ pub async fn save_settings(username: String, server: String) -> Result<String, b
 
 #[tauri::command]
 pub async fn send_message(
-    receiver: String,
+    receivers: Vec<String>,
     text: String,
     state: tauri::State<'_, CommandState>,
 ) -> Result<(), bool> {
-    tracing::debug!("invoking send_message to {}", &receiver);
+    tracing::debug!("invoking send_message to {:?}", &receivers);
 
-    let cmd = Command::SendPrompt(receiver, text);
+    let cmd = Command::SendPrompt(receivers, text);
+    let res = state.tx.send(cmd);
+
+    if res.is_err() {
+        Err(true)
+    } else {
+        Ok(())
+    }
+}
+
+#[tauri::command]
+pub async fn typing(receiver: String, state: tauri::State<'_, CommandState>) -> Result<(), bool> {
+    let cmd = Command::Typing(receiver);
     let res = state.tx.send(cmd);
 
     if res.is_err() {
@@ -0,0 +1,116 @@
+use crate::settings;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use websocket::e2e::Identity;
+
+const KEY_STORE_FILE: &str = "x-ferris-say-keys.json";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredKeys {
+    identity: String,
+    // user -> the public key we first saw from them, or the latest one after a change
+    #[serde(default)]
+    peers: BTreeMap<String, String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PeerKey {
+    New,
+    Known,
+    Changed,
+}
+
+/// Our identity key and the public keys of the peers we talked to, kept next
+/// to the settings.
+pub struct KeyStore {
+    identity: Identity,
+    peers: BTreeMap<String, String>,
+    path: PathBuf,
+}
+
+impl KeyStore {
+    /// loads the key store, creating one with a fresh identity the first time.
+    pub fn load_or_generate() -> anyhow::Result<Self> {
+        let path = settings::system_file_path(KEY_STORE_FILE)?;
+
+        if path.exists() {
+            let stored: StoredKeys = serde_json::from_str(&fs::read_to_string(&path)?)?;
+
+            return Ok(Self {
+                identity: Identity::from_base64(&stored.identity)?,
+                peers: stored.peers,
+                path,
+            });
+        }
+
+        let key_store = Self {
+            identity: Identity::generate(),
+            peers: BTreeMap::new(),
+            path,
+        };
+        key_store.save()?;
+
+        tracing::info!("generated a new identity key");
+
+        Ok(key_store)
+    }
+
+    pub fn identity(&self) -> &Identity {
+        &self.identity
+    }
+
+    /// remembers the key we got for the user, saying how it compares to the one we knew.
+    pub fn see_peer(&mut self, user: &str, key: &str) -> PeerKey {
+        let seen = match self.peers.get(user) {
+            Some(known) if known == key => return PeerKey::Known,
+            Some(_) => PeerKey::Changed,
+            None => PeerKey::New,
+        };
+
+        self.peers.insert(user.to_string(), key.to_string());
+
+        if let Err(e) = self.save() {
+            tracing::error!("failed to save key store: {}", e);
+        }
+
+        seen
+    }
+
+    // only we get to read the file, it holds our secret key. written to a temporary
+    // file first so a crash never leaves us with half a key.
+    fn save(&self) -> anyhow::Result<()> {
+        let stored = StoredKeys {
+            identity: self.identity.to_base64(),
+            peers: self.peers.clone(),
+        };
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options.open(&tmp_path)?;
+
+        // the mode only applies to new files, a leftover one may have another
+        #[cfg(unix)]
+        fs::set_permissions(
+            &tmp_path,
+            std::os::unix::fs::PermissionsExt::from_mode(0o600),
+        )?;
+
+        file.write_all(serde_json::to_string(&stored)?.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        Ok(())
+    }
+}
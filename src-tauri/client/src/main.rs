@@ -2,8 +2,10 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod command;
+mod keys;
 mod settings;
 
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 use websocket::client;
@@ -15,11 +17,22 @@ use tauri::{
 use tokio::{select, time};
 
 use command::Command;
+use keys::{KeyStore, PeerKey};
+use serde::Serialize;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::sync::{mpsc, Mutex};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
-use websocket::message::MessageContent;
+use websocket::message::{
+    DeliveryStatus, MessageContent, PresenceStatus, Priority, UserPresence, UsernameError,
+};
+
+const PRESENCE_STATUSES: [(&str, PresenceStatus); 4] = [
+    ("status_available", PresenceStatus::Available),
+    ("status_away", PresenceStatus::Away),
+    ("status_busy", PresenceStatus::Busy),
+    ("status_dnd", PresenceStatus::DoNotDisturb),
+];
 
 fn main() -> anyhow::Result<()> {
     tracing_subscriber::registry()
@@ -37,7 +50,8 @@ fn main() -> anyhow::Result<()> {
         .manage(command::CommandState::new(command_handler_tx))
         .invoke_handler(tauri::generate_handler![
             command::send_message,
-            command::save_settings
+            command::save_settings,
+            command::typing
         ])
         .on_system_tray_event(tray_menu_handler(system_tray_tx))
         .on_window_event(|event| {
@@ -77,6 +91,26 @@ fn tray_menu_handler(command_tx: UnboundedSender<Command>) -> impl Fn(&AppHandle
                 if let Err(e) = command_tx.send(Command::ListUsers) {
                     tracing::error!("failed to send list command: {}", e);
                 }
+            } else if let Some((_, status)) = PRESENCE_STATUSES.iter().find(|(s, _)| *s == id) {
+                if let Err(e) = command_tx.send(Command::SetPresence(*status)) {
+                    tracing::error!("failed to send set presence command: {}", e);
+                }
+            } else if let Some(user) = id.strip_prefix("block:") {
+                if let Err(e) = command_tx.send(Command::BlockUser(user.to_string())) {
+                    tracing::error!("failed to send block command: {}", e);
+                }
+            } else if let Some(user) = id.strip_prefix("unblock:") {
+                if let Err(e) = command_tx.send(Command::UnblockUser(user.to_string())) {
+                    tracing::error!("failed to send unblock command: {}", e);
+                }
+            } else if let Some(user) = id.strip_prefix("mute:") {
+                if let Err(e) = command_tx.send(Command::MuteUser(user.to_string())) {
+                    tracing::error!("failed to send mute command: {}", e);
+                }
+            } else if let Some(user) = id.strip_prefix("unmute:") {
+                if let Err(e) = command_tx.send(Command::UnmuteUser(user.to_string())) {
+                    tracing::error!("failed to send unmute command: {}", e);
+                }
             } else if id == "send" {
                 let window = app.get_window("main").unwrap();
 
@@ -101,6 +135,87 @@ fn show_window(w: &Window) {
     w.show().unwrap();
 }
 
+// how a prompt is shown, depending on its priority
+struct PromptStyle {
+    duration: Duration,
+    steal_focus: bool,
+    sound: bool,
+}
+
+impl PromptStyle {
+    fn for_priority(priority: Priority) -> Self {
+        match priority {
+            Priority::Low => Self {
+                duration: Duration::from_secs(3),
+                steal_focus: false,
+                sound: false,
+            },
+            Priority::Normal => Self {
+                duration: Duration::from_secs(5),
+                steal_focus: true,
+                sound: false,
+            },
+            Priority::Urgent => Self {
+                duration: Duration::from_secs(15),
+                steal_focus: true,
+                sound: true,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct PromptEvent<'a> {
+    text: &'a str,
+    duration_ms: u64,
+    sound: bool,
+}
+
+fn show_prompt(w: &Window, style: &PromptStyle) {
+    w.center().unwrap();
+
+    if style.steal_focus {
+        w.set_focus().unwrap();
+    }
+
+    w.show().unwrap();
+}
+
+fn display_prompt(w: &Window, text: &str, priority: Priority, character: &Option<String>) {
+    let style = PromptStyle::for_priority(priority);
+
+    show_prompt(w, &style);
+    w.emit_all("character", character).unwrap();
+    w.emit_all(
+        "chat_message",
+        PromptEvent {
+            text,
+            duration_ms: style.duration.as_millis() as u64,
+            sound: style.sound,
+        },
+    )
+    .unwrap();
+}
+
+// prompts from muted senders wait in the window for whenever it is opened next,
+// without popping it up over whatever is on screen
+fn keep_muted_prompt(w: &Window, text: &str, character: &Option<String>) {
+    if w.is_visible().unwrap_or(false) {
+        return;
+    }
+
+    w.emit_all("character", character).unwrap();
+    w.emit_all("muted_message", text).unwrap();
+}
+
+fn key_store_error(e: &anyhow::Error) -> String {
+    format!("encryption keys could not be loaded, nothing will be sent until they are: {e}")
+}
+
+fn key_change_warning(user: &str) -> String {
+    format!("{user}'s encryption key changed, make sure it's really them")
+}
+
 fn init_client(app: &mut App, command_rx: UnboundedReceiver<Command>) {
     let main_window = app.get_window("main").unwrap();
     let init_window = app.get_window("init-config").unwrap();
@@ -121,18 +236,26 @@ fn init_client(app: &mut App, command_rx: UnboundedReceiver<Command>) {
             main_window.hide().unwrap();
             show_window(&init_window);
         } else {
-            spawn_tokio_ws(config.username, config.server, main_window, app, command_rx);
+            spawn_tokio_ws(
+                config.username,
+                config.server,
+                config.e2e,
+                main_window,
+                app,
+                command_rx,
+            );
         }
     }
 
     app.tray_handle()
-        .set_menu(init_menu_items(&vec![]))
+        .set_menu(init_menu_items(&[], &[], &[]))
         .unwrap();
 }
 
 fn spawn_tokio_ws(
     username: String,
     server: String,
+    e2e: bool,
     window: Window,
     app: &mut App,
     command_chan: UnboundedReceiver<Command>,
@@ -147,6 +270,25 @@ fn spawn_tokio_ws(
 
     let _handle = tauri::async_runtime::spawn(async move {
         let mut retry_wait = time::interval(Duration::from_secs(5));
+        // carried over reconnects so the server can replay what we missed and
+        // we can resend what it never confirmed
+        let mut last_seq = None;
+        let mut unacknowledged = Vec::new();
+        // sender and id of the prompt on screen, so a recall of it can hide it
+        let mut shown_prompt: Option<(String, Option<String>)> = None;
+        // only loaded in e2e mode, otherwise we neither publish a key nor open sealed prompts
+        let mut key_store = if e2e {
+            KeyStore::load_or_generate()
+                .map_err(|e| {
+                    tracing::error!("failed to load key store: {}", e);
+                    display_prompt(&window, &key_store_error(&e), Priority::Urgent, &None);
+                })
+                .ok()
+        } else {
+            None
+        };
+        // receiver -> prompts waiting for the receiver's public key to be sealed
+        let mut pending_sealed: HashMap<String, Vec<String>> = HashMap::new();
         loop {
             let cancel_app_handle = Arc::clone(&app_handle);
             let tray_handle = Arc::clone(&tray_handle);
@@ -154,9 +296,33 @@ fn spawn_tokio_ws(
             retry_wait.tick().await;
 
             let ws_chat_handle =
-                client::init_client(username.to_string(), server.to_string()).await;
+                client::ChatHandle::resume(username.to_string(), server.to_string(), last_seq)
+                    .await;
 
             if let Err(e) = ws_chat_handle {
+                // our old connection may not be gone on the server yet, it lets go
+                // of the name once it stops hearing from it
+                if last_seq.is_some()
+                    && e.downcast_ref::<UsernameError>() == Some(&UsernameError::AlreadyTaken)
+                {
+                    tracing::warn!("name still taken by our previous connection, retrying");
+
+                    continue;
+                }
+
+                if let Some(username_error) = e.downcast_ref::<UsernameError>() {
+                    tracing::error!("server refused username: {}", username_error);
+
+                    // retrying with the same name won't help, ask for another one
+                    let init_window = app_handle.get_window("init-config").unwrap();
+                    show_window(&init_window);
+                    init_window
+                        .emit("username_rejected", username_error.to_string())
+                        .unwrap();
+
+                    break;
+                }
+
                 tracing::error!("failed to initialize websocket: {:?}", e);
 
                 continue;
@@ -174,12 +340,53 @@ fn spawn_tokio_ws(
                 cancel_app_handle.exit(1);
             });
 
-            let message_chat_handle = ws_chat_handle.lock().await;
-            let mut message_receiver = message_chat_handle.get_receiver();
-            // drop the MutexGuard to unlock it
-            drop(message_chat_handle);
+            // queued, a burst of messages must not swallow prompts or key replies
+            let mut message_receiver = ws_chat_handle
+                .lock()
+                .await
+                .take_queued_receiver()
+                .expect("a new connection has its receiver");
 
             let mut refresh_interval = time::interval(Duration::from_secs(10));
+            let mut blocked_users = Vec::new();
+            let mut muted_users = Vec::new();
+
+            if let Err(e) = ws_chat_handle
+                .lock()
+                .await
+                .retry_unacknowledged(std::mem::take(&mut unacknowledged))
+                .await
+            {
+                tracing::error!("failed to resend unacknowledged messages: {}", e);
+            }
+
+            if let Err(e) = ws_chat_handle.lock().await.list_blocked_users().await {
+                tracing::error!("failed to send list blocked users command: {}", e);
+            }
+
+            if let Err(e) = ws_chat_handle.lock().await.list_muted_users().await {
+                tracing::error!("failed to send list muted users command: {}", e);
+            }
+
+            if let Some(key_store) = &key_store {
+                let public_key = key_store.identity().public_key();
+
+                if let Err(e) = ws_chat_handle.lock().await.publish_key(public_key).await {
+                    tracing::error!("failed to publish public key: {}", e);
+                }
+
+                // answers to what we asked the last connection are gone with it
+                for receiver in pending_sealed.keys() {
+                    if let Err(e) = ws_chat_handle
+                        .lock()
+                        .await
+                        .get_public_key(receiver.clone())
+                        .await
+                    {
+                        tracing::error!("failed to ask for public key: {}", e);
+                    }
+                }
+            }
 
             loop {
                 let mut command_chan = command_chan.lock().await;
@@ -208,13 +415,74 @@ fn spawn_tokio_ws(
                                 }
                             }
 
-                            Command::SendPrompt(receiver, text) => {
-                                if let Err(e) = ws_chat_handle.lock().await
-                                .send_text(receiver, text).await{
+                            // sealed once the receiver's current key arrives, so a
+                            // changed key is noticed before anything is sent with it
+                            Command::SendPrompt(receivers, text) if e2e => {
+                                if key_store.is_none() {
+                                    tracing::error!("not sending, there is no key store to seal prompts with");
+                                    display_prompt(&window, "not sent, encryption keys could not be loaded", Priority::Normal, &None);
+                                } else {
+                                    for receiver in receivers {
+                                        pending_sealed.entry(receiver.clone()).or_default().push(text.clone());
+
+                                        if let Err(e) = ws_chat_handle.lock().await.get_public_key(receiver).await {
+                                            tracing::error!("failed to ask for public key: {}", e);
+                                        }
+                                    }
+                                }
+                            }
+
+                            Command::SendPrompt(mut receivers, text) => {
+                                let result = if receivers.len() == 1 {
+                                    ws_chat_handle.lock().await
+                                    .send_text(receivers.remove(0), text).await
+                                } else {
+                                    ws_chat_handle.lock().await
+                                    .multicast_text(receivers, text).await
+                                };
+
+                                if let Err(e) = result {
                                     tracing::error!("failed to send text: {}", e);
                                 }
                             }
 
+                            Command::SetPresence(status) => {
+                                if let Err(e) = ws_chat_handle.lock().await
+                                .set_presence(status, None).await{
+                                    tracing::error!("failed to set presence: {}", e);
+                                }
+                            }
+
+                            Command::BlockUser(user) => {
+                                if let Err(e) = ws_chat_handle.lock().await.block_user(user).await {
+                                    tracing::error!("failed to block user: {}", e);
+                                }
+                            }
+
+                            Command::Typing(receiver) => {
+                                if let Err(e) = ws_chat_handle.lock().await.send_typing(receiver).await {
+                                    tracing::debug!("failed to send typing indicator: {}", e);
+                                }
+                            }
+
+                            Command::UnblockUser(user) => {
+                                if let Err(e) = ws_chat_handle.lock().await.unblock_user(user).await {
+                                    tracing::error!("failed to unblock user: {}", e);
+                                }
+                            }
+
+                            Command::MuteUser(user) => {
+                                if let Err(e) = ws_chat_handle.lock().await.mute_user(user).await {
+                                    tracing::error!("failed to mute user: {}", e);
+                                }
+                            }
+
+                            Command::UnmuteUser(user) => {
+                                if let Err(e) = ws_chat_handle.lock().await.unmute_user(user).await {
+                                    tracing::error!("failed to unmute user: {}", e);
+                                }
+                            }
+
                             // Command::Reconnect(_) => {
                             //     tracing::debug!("received reconnect command");
                             //     command_app_handle.restart();
@@ -222,41 +490,130 @@ fn spawn_tokio_ws(
                         }
                     }
 
-                    received_message = message_receiver.changed() => {
-                        if let Err(e) = received_message {
-                            tracing::warn!(
-                                "message receiver returned error (sender probably dropped): {:?}",
-                                e
-                            );
+                    received_message = message_receiver.recv() => {
+                        let Some(msg) = received_message else {
+                            tracing::warn!("message receiver closed, the connection is gone");
                             break;
-                        }
+                        };
 
                         {
-                            let msg = message_receiver.borrow_and_update();
-
-                            let msg = &*msg;
-
                             // handle received message
                             {
                                 match &msg.content {
                                     MessageContent::ListUsers(list) => {
                                         let online_users_without_self = list
                                             .iter()
-                                            .filter_map(|s| {
-                                                if username.as_str() != s.as_str() {
-                                                    Some(s.to_string())
-                                                } else {
-                                                    None
-                                                }
-                                            })
+                                            .filter(|s| username.as_str() != s.name.as_str())
+                                            .cloned()
+                                            .collect::<Vec<_>>();
+                                        let online_user_names = online_users_without_self
+                                            .iter()
+                                            .map(|s| s.name.clone())
                                             .collect::<Vec<_>>();
 
-                                        tray_handle.set_menu(init_menu_items(&online_users_without_self)).unwrap();
-                                        window.emit_all("online_users", &online_users_without_self).unwrap();
+                                        tray_handle.set_menu(init_menu_items(&online_users_without_self, &blocked_users, &muted_users)).unwrap();
+                                        window.emit_all("online_users", &online_user_names).unwrap();
+                                    }
+                                    MessageContent::BlockedUsers(list) => {
+                                        blocked_users = list.clone();
+                                        refresh_interval.reset_immediately();
+                                    }
+                                    MessageContent::MutedUsers(list) => {
+                                        muted_users = list.clone();
+                                        refresh_interval.reset_immediately();
+                                    }
+                                    MessageContent::PresenceChanged(_) => {
+                                        // refresh the whole list instead of patching the tray menu
+                                        refresh_interval.reset_immediately();
+                                    }
+                                    MessageContent::DeliveryReport(results) => {
+                                        for (receiver, status) in results {
+                                            if *status != DeliveryStatus::Delivered {
+                                                tracing::warn!("prompt to {receiver} was not delivered: {status:?}");
+                                            }
+                                        }
+                                    }
+                                    MessageContent::Typing => {
+                                        window.emit_all("typing", &msg.from).unwrap();
+                                    }
+                                    MessageContent::Prompt(text) if msg.muted => {
+                                        shown_prompt = Some((msg.from.clone(), msg.key.clone()));
+                                        keep_muted_prompt(&window, text, &msg.character);
                                     }
                                     MessageContent::Prompt(text) => {
-                                        show_window(&window);
-                                        window.emit_all("chat_message", text).unwrap();
+                                        shown_prompt = Some((msg.from.clone(), msg.key.clone()));
+                                        display_prompt(&window, text, msg.priority, &msg.character);
+                                    }
+                                    MessageContent::SealedPrompt(sealed) => {
+                                        let Some(key_store) = key_store.as_mut() else {
+                                            tracing::warn!("can't open sealed prompt from {}, e2e is off", msg.from);
+                                            continue;
+                                        };
+
+                                        let changed = key_store.see_peer(&msg.from, &sealed.sender_key) == PeerKey::Changed;
+
+                                        match key_store.identity().open(&msg.from, username.as_str(), sealed) {
+                                            Ok(text) => {
+                                                // a separate notice would be replaced by the prompt right away
+                                                let text = if changed {
+                                                    format!("{}\n\n{text}", key_change_warning(&msg.from))
+                                                } else {
+                                                    text
+                                                };
+
+                                                shown_prompt = Some((msg.from.clone(), msg.key.clone()));
+
+                                                if msg.muted {
+                                                    keep_muted_prompt(&window, &text, &msg.character);
+                                                } else {
+                                                    display_prompt(&window, &text, msg.priority, &msg.character);
+                                                }
+                                            }
+                                            Err(e) => tracing::warn!("{}", e),
+                                        }
+                                    }
+                                    MessageContent::PublicKey(user, key) => {
+                                        let pending = pending_sealed.remove(user).unwrap_or_default();
+
+                                        match (key, key_store.as_mut()) {
+                                            (Some(key), Some(key_store)) => {
+                                                if key_store.see_peer(user, key) == PeerKey::Changed {
+                                                    tracing::warn!("public key of {user} changed");
+                                                    display_prompt(&window, &key_change_warning(user), Priority::Normal, &None);
+                                                }
+
+                                                for text in pending {
+                                                    let sealed = key_store.identity().seal(username.as_str(), user, key, &text);
+
+                                                    let result = match sealed {
+                                                        Ok(sealed) => ws_chat_handle.lock().await.send_sealed(user.clone(), sealed).await,
+                                                        Err(e) => Err(e),
+                                                    };
+
+                                                    if let Err(e) = result {
+                                                        tracing::error!("failed to send sealed prompt: {}", e);
+                                                    }
+                                                }
+                                            }
+                                            _ if !pending.is_empty() => {
+                                                tracing::warn!("{user} has no public key, {} prompts were not sent", pending.len());
+
+                                                let notice = format!("{user} can't receive encrypted prompts, nothing was sent");
+                                                display_prompt(&window, &notice, Priority::Normal, &None);
+                                            }
+                                            _ => {}
+                                        }
+                                    }
+                                    MessageContent::Recall(id) => {
+                                        let recalled = shown_prompt.as_ref().is_some_and(|(from, key)| {
+                                            *from == msg.from && key.as_ref() == Some(id)
+                                        });
+
+                                        if recalled {
+                                            shown_prompt = None;
+                                            window.hide().unwrap();
+                                            window.emit_all("recall", id).unwrap();
+                                        }
                                     }
                                     _ => {}
                                 }
@@ -265,11 +622,19 @@ fn spawn_tokio_ws(
                     }
                 }
             }
+
+            let chat_handle = ws_chat_handle.lock().await;
+            last_seq = Some(chat_handle.last_seq());
+            unacknowledged = chat_handle.unacknowledged();
         }
     });
 }
 
-fn init_menu_items(online_users: &Vec<String>) -> SystemTrayMenu {
+fn init_menu_items(
+    online_users: &[UserPresence],
+    blocked_users: &[String],
+    muted_users: &[String],
+) -> SystemTrayMenu {
     let quit = CustomMenuItem::new("quit".to_string(), "Quit");
     let refresh = CustomMenuItem::new("refresh".to_string(), "Refresh online users");
     let send = CustomMenuItem::new("send".to_string(), "Send a message");
@@ -279,13 +644,40 @@ fn init_menu_items(online_users: &Vec<String>) -> SystemTrayMenu {
     let mut online_users_menu_item = SystemTrayMenu::new();
 
     for user in online_users {
-        let user_menu_item = CustomMenuItem::new(user, user);
-
-        online_users_menu_item = online_users_menu_item.add_item(user_menu_item);
+        let title = match &user.presence.status_text {
+            Some(text) => format!("{} ({}: {})", user.name, user.presence.status, text),
+            None => format!("{} ({})", user.name, user.presence.status),
+        };
+        let block_item = if blocked_users.contains(&user.name) {
+            CustomMenuItem::new(format!("unblock:{}", user.name), "Unblock")
+        } else {
+            CustomMenuItem::new(format!("block:{}", user.name), "Block")
+        };
+        let mute_item = if muted_users.contains(&user.name) {
+            CustomMenuItem::new(format!("unmute:{}", user.name), "Unmute")
+        } else {
+            CustomMenuItem::new(format!("mute:{}", user.name), "Mute")
+        };
+        let user_sub_menu = SystemTraySubmenu::new(
+            title,
+            SystemTrayMenu::new()
+                .add_item(block_item)
+                .add_item(mute_item),
+        );
+
+        online_users_menu_item = online_users_menu_item.add_submenu(user_sub_menu);
     }
 
     let online_users_sub_menu = SystemTraySubmenu::new("Online Users", online_users_menu_item);
 
+    let mut status_menu_item = SystemTrayMenu::new();
+
+    for (id, status) in PRESENCE_STATUSES {
+        status_menu_item = status_menu_item.add_item(CustomMenuItem::new(id, status.to_string()));
+    }
+
+    let status_sub_menu = SystemTraySubmenu::new("Status", status_menu_item);
+
     SystemTrayMenu::new()
         .add_item(quit)
         .add_native_item(SystemTrayMenuItem::Separator)
@@ -297,5 +689,7 @@ fn init_menu_items(online_users: &Vec<String>) -> SystemTrayMenu {
         .add_native_item(SystemTrayMenuItem::Separator)
         .add_item(refresh)
         .add_native_item(SystemTrayMenuItem::Separator)
+        .add_submenu(status_sub_menu)
+        .add_native_item(SystemTrayMenuItem::Separator)
         .add_submenu(online_users_sub_menu)
 }
@@ -11,6 +11,9 @@ use std::path::{Path, PathBuf};
 pub struct Settings {
     pub username: String,
     pub server: String,
+    /// seal outgoing prompts so only their receivers can read them.
+    #[serde(default)]
+    pub e2e: bool,
 }
 
 lazy_static! {
@@ -28,9 +31,22 @@ lazy_static! {
     };
 }
 
+/// path of a file that lives next to the settings file, e.g. the key store.
+pub fn system_file_path(file_name: &str) -> anyhow::Result<PathBuf> {
+    let config_dir = DEFAULT_CONFIG_PATH
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("no config directory on this system"))?;
+
+    Ok(config_dir.join(file_name))
+}
+
 impl Settings {
-    pub fn new(username: String, server: String) -> Self {
-        Settings { username, server }
+    pub fn new(username: String, server: String, e2e: bool) -> Self {
+        Settings {
+            username,
+            server,
+            e2e,
+        }
     }
 
     pub fn from_file(file_name: &str) -> anyhow::Result<Self> {
@@ -0,0 +1,260 @@
+mod report;
+
+use clap::{Parser, ValueEnum};
+use report::{ClientStats, Report};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::time::{self, Instant};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use websocket::client::ChatHandle;
+use websocket::message::{ChatMessage, MessageContent};
+
+/// Spawns a crowd of chat clients against a server and reports how it held up
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Address of the server under test, e.g. 127.0.0.1:8080
+    #[arg(short, long)]
+    server: String,
+
+    /// Number of clients to connect
+    #[arg(short, long, default_value_t = 100)]
+    users: usize,
+
+    /// Who sends what to whom
+    #[arg(long, value_enum, default_value_t = Pattern::Pairwise)]
+    pattern: Pattern,
+
+    /// Messages every client sends per second
+    #[arg(short, long, default_value_t = 1.0)]
+    rate: f64,
+
+    /// How long to send for, in seconds
+    #[arg(short, long, default_value_t = 30)]
+    duration: u64,
+
+    /// How long to keep waiting for replies after sending stopped, in seconds
+    #[arg(long, default_value_t = 2)]
+    drain: u64,
+
+    /// Prefix of the generated usernames
+    #[arg(long, default_value = "load-")]
+    prefix: String,
+
+    /// Print the report as json
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Pattern {
+    /// every client talks to one partner
+    Pairwise,
+    /// every message goes to all the other clients
+    Broadcast,
+    /// everyone keeps asking for the list of online users
+    ListStorm,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_default())
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .init();
+
+    let args = Args::parse();
+
+    anyhow::ensure!(args.users > 0, "need at least one user");
+    anyhow::ensure!(args.rate > 0.0, "rate must be positive");
+
+    let names = (0..args.users)
+        .map(|i| format!("{}{i}", args.prefix))
+        .collect::<Vec<_>>();
+
+    let mut connecting = Vec::with_capacity(args.users);
+    for name in &names {
+        connecting.push(tokio::spawn(ChatHandle::new(
+            name.clone(),
+            args.server.clone(),
+        )));
+    }
+
+    let mut handles = Vec::with_capacity(args.users);
+    let mut connect_errors = 0;
+    for (name, connection) in names.iter().zip(connecting) {
+        match connection.await? {
+            Ok(mut handle) => {
+                let receiver = handle.take_queued_receiver().unwrap();
+                handles.push((handle, receiver));
+            }
+            Err(e) => {
+                tracing::warn!("{name} failed to connect: {e}");
+                connect_errors += 1;
+            }
+        }
+    }
+
+    tracing::info!("{} clients connected, starting traffic", handles.len());
+
+    let names = Arc::new(names);
+    // prompts carry the time they were sent, relative to this
+    let epoch = Instant::now();
+    let stop_sending = epoch + Duration::from_secs(args.duration);
+    let stop = stop_sending + Duration::from_secs(args.drain);
+
+    let mut clients = Vec::with_capacity(handles.len());
+    for (index, connection) in handles.into_iter().enumerate() {
+        let targets = targets(args.pattern, index, &names);
+
+        clients.push(tokio::spawn(run_client(
+            connection,
+            args.pattern,
+            targets,
+            Duration::from_secs_f64(1.0 / args.rate),
+            epoch,
+            stop_sending,
+            stop,
+        )));
+    }
+
+    let mut stats = Vec::with_capacity(clients.len());
+    for client in clients {
+        stats.push(client.await?);
+    }
+
+    let report = Report::new(
+        format!("{:?}", args.pattern),
+        args.users,
+        connect_errors,
+        Duration::from_secs(args.duration),
+        stats,
+    );
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("{report}");
+    }
+
+    Ok(())
+}
+
+// who the client at `index` sends its prompts to
+fn targets(pattern: Pattern, index: usize, names: &[String]) -> Vec<String> {
+    match pattern {
+        // 0 <-> 1, 2 <-> 3, ... and the odd one out talks to itself
+        Pattern::Pairwise => {
+            let partner = if index ^ 1 < names.len() {
+                index ^ 1
+            } else {
+                index
+            };
+
+            vec![names[partner].clone()]
+        }
+        Pattern::Broadcast => names
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != index)
+            .map(|(_, name)| name.clone())
+            .collect(),
+        Pattern::ListStorm => Vec::new(),
+    }
+}
+
+async fn run_client(
+    (mut handle, mut receiver): (ChatHandle, UnboundedReceiver<ChatMessage>),
+    pattern: Pattern,
+    targets: Vec<String>,
+    period: Duration,
+    epoch: Instant,
+    stop_sending: Instant,
+    stop: Instant,
+) -> ClientStats {
+    let mut stats = ClientStats::new();
+    let mut ticker = time::interval(period);
+    // when our outstanding user list requests were sent, the server answers them in order
+    let mut list_requests = VecDeque::new();
+
+    let deadline = time::sleep_until(stop);
+    tokio::pin!(deadline);
+
+    loop {
+        tokio::select! {
+            _ = &mut deadline => break,
+
+            now = ticker.tick(), if Instant::now() < stop_sending => {
+                let sent = match pattern {
+                    Pattern::ListStorm => {
+                        list_requests.push_back(now);
+                        handle.list_users().await.map(|_| 1)
+                    }
+                    Pattern::Pairwise | Pattern::Broadcast => {
+                        send_prompts(&mut handle, &targets, epoch).await
+                    }
+                };
+
+                match sent {
+                    Ok(count) => stats.sent += count,
+                    Err(e) => {
+                        tracing::debug!("failed to send: {e}");
+                        stats.errors += 1;
+                    }
+                }
+            }
+
+            msg = receiver.recv() => {
+                let Some(msg) = msg else {
+                    tracing::debug!("connection closed by the server");
+                    stats.errors += 1;
+                    break;
+                };
+
+                match msg.content {
+                    MessageContent::Prompt(text) => {
+                        stats.received += 1;
+
+                        if let Ok(sent_at) = text.parse::<u64>() {
+                            let sent_at = epoch + Duration::from_micros(sent_at);
+                            stats.record_latency(Instant::now() - sent_at);
+                        }
+                    }
+                    MessageContent::ListUsers(_) => {
+                        stats.received += 1;
+
+                        if let Some(sent_at) = list_requests.pop_front() {
+                            stats.record_latency(sent_at.elapsed());
+                        }
+                    }
+                    MessageContent::Error(e) => {
+                        tracing::debug!("server returned error: {e:?}");
+                        stats.errors += 1;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    let _ = handle.close().await;
+
+    stats
+}
+
+async fn send_prompts(
+    handle: &mut ChatHandle,
+    targets: &[String],
+    epoch: Instant,
+) -> anyhow::Result<u64> {
+    for target in targets {
+        let sent_at = epoch.elapsed().as_micros().to_string();
+
+        handle.send_text(target.clone(), sent_at).await?;
+    }
+
+    Ok(targets.len() as u64)
+}
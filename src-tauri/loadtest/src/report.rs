@@ -0,0 +1,214 @@
+use hdrhistogram::Histogram;
+use serde::Serialize;
+use std::fmt;
+use std::time::Duration;
+
+/// What one client saw during the run, merged into the report at the end.
+pub struct ClientStats {
+    /// round trip times in microseconds
+    pub latencies: Histogram<u64>,
+    pub sent: u64,
+    pub received: u64,
+    pub errors: u64,
+}
+
+impl ClientStats {
+    pub fn new() -> Self {
+        Self {
+            // one microsecond to one minute with 3 significant digits
+            latencies: Histogram::new_with_bounds(1, 60_000_000, 3).unwrap(),
+            sent: 0,
+            received: 0,
+            errors: 0,
+        }
+    }
+
+    pub fn record_latency(&mut self, latency: Duration) {
+        self.latencies
+            .saturating_record(latency.as_micros().try_into().unwrap_or(u64::MAX));
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub pattern: String,
+    pub users: usize,
+    pub connected: usize,
+    pub connect_errors: usize,
+    pub duration_secs: f64,
+    pub sent: u64,
+    pub received: u64,
+    pub errors: u64,
+    /// errors over everything we tried to send
+    pub error_rate: f64,
+    /// received messages per second
+    pub throughput: f64,
+    pub latency_ms: Latency,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Latency {
+    pub min: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub p999: f64,
+    pub max: f64,
+}
+
+impl Report {
+    pub fn new(
+        pattern: String,
+        users: usize,
+        connect_errors: usize,
+        duration: Duration,
+        clients: Vec<ClientStats>,
+    ) -> Self {
+        let connected = clients.len();
+        let mut latencies = ClientStats::new().latencies;
+        let (mut sent, mut received, mut errors) = (0, 0, 0);
+
+        for client in clients {
+            // clamped like single recordings, a client's histogram could have other bounds
+            for value in client.latencies.iter_recorded() {
+                latencies.saturating_record_n(value.value_iterated_to(), value.count_at_value());
+            }
+
+            sent += client.sent;
+            received += client.received;
+            errors += client.errors;
+        }
+
+        let ms = |micros: u64| micros as f64 / 1000.0;
+        let duration_secs = duration.as_secs_f64();
+
+        Self {
+            pattern,
+            users,
+            connected,
+            connect_errors,
+            duration_secs,
+            sent,
+            received,
+            errors,
+            error_rate: if sent + errors == 0 {
+                0.0
+            } else {
+                errors as f64 / (sent + errors) as f64
+            },
+            throughput: if duration_secs > 0.0 {
+                received as f64 / duration_secs
+            } else {
+                0.0
+            },
+            latency_ms: Latency {
+                min: ms(latencies.min()),
+                mean: latencies.mean() / 1000.0,
+                p50: ms(latencies.value_at_quantile(0.5)),
+                p90: ms(latencies.value_at_quantile(0.9)),
+                p99: ms(latencies.value_at_quantile(0.99)),
+                p999: ms(latencies.value_at_quantile(0.999)),
+                max: ms(latencies.max()),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let latency = &self.latency_ms;
+
+        writeln!(f, "pattern:     {}", self.pattern)?;
+        writeln!(
+            f,
+            "users:       {} connected, {} failed to connect",
+            self.connected, self.connect_errors
+        )?;
+        writeln!(f, "duration:    {:.1}s", self.duration_secs)?;
+        writeln!(
+            f,
+            "messages:    {} sent, {} received, {} errors ({:.2}%)",
+            self.sent,
+            self.received,
+            self.errors,
+            self.error_rate * 100.0
+        )?;
+        writeln!(f, "throughput:  {:.1} msg/s", self.throughput)?;
+        write!(
+            f,
+            "latency:     min {:.2}ms, mean {:.2}ms, p50 {:.2}ms, p90 {:.2}ms, p99 {:.2}ms, p99.9 {:.2}ms, max {:.2}ms",
+            latency.min, latency.mean, latency.p50, latency.p90, latency.p99, latency.p999, latency.max
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(latencies_ms: &[u64], sent: u64, received: u64, errors: u64) -> ClientStats {
+        let mut stats = ClientStats::new();
+
+        for ms in latencies_ms {
+            stats.record_latency(Duration::from_millis(*ms));
+        }
+
+        stats.sent = sent;
+        stats.received = received;
+        stats.errors = errors;
+
+        stats
+    }
+
+    fn report(duration: Duration, clients: Vec<ClientStats>) -> Report {
+        Report::new("echo".to_string(), 3, 1, duration, clients)
+    }
+
+    // histogram values are only exact to 3 significant digits
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() <= expected * 0.001,
+            "{actual} is not {expected}"
+        );
+    }
+
+    #[test]
+    fn merges_client_stats() {
+        let report = report(
+            Duration::from_secs(2),
+            vec![client(&[1, 2, 3], 10, 8, 1), client(&[4], 5, 4, 0)],
+        );
+
+        assert_eq!((report.connected, report.connect_errors), (2, 1));
+        assert_eq!((report.sent, report.received, report.errors), (15, 12, 1));
+        assert_eq!(report.error_rate, 1.0 / 16.0);
+        assert_eq!(report.throughput, 6.0);
+
+        assert_close(report.latency_ms.min, 1.0);
+        assert_close(report.latency_ms.p50, 2.0);
+        assert_close(report.latency_ms.p99, 4.0);
+        assert_close(report.latency_ms.max, 4.0);
+        assert_close(report.latency_ms.mean, 2.5);
+    }
+
+    #[test]
+    fn reports_nothing_for_an_empty_run() {
+        let report = report(Duration::ZERO, Vec::new());
+
+        assert_eq!(report.error_rate, 0.0);
+        assert_eq!(report.throughput, 0.0);
+        assert_eq!(report.latency_ms.max, 0.0);
+    }
+
+    #[test]
+    fn clamps_latencies_out_of_bounds() {
+        let mut stats = client(&[], 1, 1, 0);
+        stats.latencies = Histogram::new(3).unwrap();
+        stats.latencies.record(120_000_000).unwrap();
+
+        let report = report(Duration::from_secs(1), vec![stats]);
+
+        assert_close(report.latency_ms.max, 60_000.0);
+    }
+}
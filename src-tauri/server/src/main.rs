@@ -1,6 +1,7 @@
 use clap::Parser;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
+use websocket::server::config::ServerConfig;
 
 /// Simple program to greet a person
 #[derive(Parser, Debug)]
@@ -9,6 +10,10 @@ struct Args {
     /// Name of the person to greet
     #[arg(short, long)]
     port: String,
+
+    /// Path to the server config file (json, toml or yaml)
+    #[arg(short, long)]
+    config: Option<String>,
 }
 
 #[tokio::main]
@@ -20,8 +25,15 @@ async fn main() -> anyhow::Result<()> {
 
     let args = Args::parse();
 
+    let config = match &args.config {
+        Some(path) => ServerConfig::from_file(path)?,
+        None => ServerConfig::default(),
+    };
+
     tokio::spawn(async move {
-        let _server = websocket::server::server_init(&args.port).await;
+        if let Err(e) = websocket::server::server_init(&args.port, config).await {
+            tracing::error!("server stopped: {:?}", e);
+        }
     });
 
     tokio::signal::ctrl_c().await.unwrap();
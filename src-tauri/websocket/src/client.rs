@@ -1,129 +1,671 @@
-use crate::message::{ChatMessage, MessageContent};
+use crate::message::{
+    ChatMessage, MessageContent, Presence, PresenceStatus, Priority, ScheduleTime, SealedPrompt,
+    UsernameError,
+};
 
 use anyhow::anyhow;
 use futures_util::stream::SplitSink;
-use futures_util::{SinkExt, StreamExt};
+use futures_util::{SinkExt, Stream, StreamExt};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::net::TcpStream;
-use tokio::sync::watch;
-use tokio::sync::watch::Receiver;
-use tokio_tungstenite::tungstenite::Message;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::{self, Message};
 use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
 
 type ClientWSSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
 
+enum Transport {
+    // shared with the reader, which sends the receipts
+    WebSocket(Arc<tokio::sync::Mutex<ClientWSSink>>),
+    // fallback for networks that strip websocket upgrades: messages for us come
+    // in as server sent events and ours go out as POST requests.
+    Sse {
+        http: reqwest::Client,
+        post_url: String,
+        reader: JoinHandle<()>,
+    },
+}
+
+// what the reader task and the handle both keep track of
+#[derive(Default)]
+struct Session {
+    // sequence number of the last prompt we got, 0 before the first one
+    last_seq: AtomicU64,
+    // whether this picks up an earlier connection, so the server should replay
+    // what we missed even if that connection never got a prompt
+    resuming: bool,
+    // keyed messages the server hasn't acknowledged yet, in the order they were sent
+    unacknowledged: Mutex<Vec<ChatMessage>>,
+    // gets every message for the user. gone once the connection is or the
+    // receiver was dropped.
+    queue: Mutex<Option<UnboundedSender<ChatMessage>>>,
+}
+
+impl Session {
+    // bookkeeping for a message from the server, returns whether it's for the user
+    fn received(&self, msg: &ChatMessage) -> bool {
+        // replays come most important first, numbering goes on from the highest
+        if let Some(seq) = msg.seq {
+            self.last_seq.fetch_max(seq, Ordering::Relaxed);
+        }
+
+        // stale prompts are worse than none, e.g. "the meeting starts now"
+        if matches!(
+            msg.content,
+            MessageContent::Prompt(_) | MessageContent::SealedPrompt(_)
+        ) && msg.is_expired()
+        {
+            return false;
+        }
+
+        if let MessageContent::Ack(key) = &msg.content {
+            self.unacknowledged
+                .lock()
+                .unwrap()
+                .retain(|m| m.key.as_ref() != Some(key));
+
+            return false;
+        }
+
+        true
+    }
+
+    // hands the message to the receiver, returns false once nobody listens anymore
+    fn enqueue(&self, msg: ChatMessage) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+
+        if queue.as_ref().is_some_and(|tx| tx.send(msg).is_ok()) {
+            return true;
+        }
+
+        *queue = None;
+
+        false
+    }
+}
+
 pub struct ChatHandle {
-    client_sink: ClientWSSink,
-    client_stream_rx: Receiver<ChatMessage>,
+    transport: Transport,
+    // there from the start, so it holds everything the server sent until it's taken
+    queued_rx: Option<UnboundedReceiver<ChatMessage>>,
     name: String,
+    session: Arc<Session>,
 }
 
 impl ChatHandle {
     pub async fn new(identity: String, server_url: String) -> anyhow::Result<Self> {
-        let ws_stream = match connect_async(format!("ws://{server_url}/ws/{identity}")).await {
-            Ok((stream, response)) => {
-                tracing::debug!(
-                    "Handshake for client has been completed with {:?}",
-                    response
-                );
+        Self::resume(identity, server_url, None).await
+    }
 
-                stream
-            }
-            Err(e) => {
-                return Err(anyhow!("WebSocket handshake failed with {e}!"));
+    /// connects again after losing the connection. prompts the server still has
+    /// that never reached us are delivered first, the most important first.
+    /// every prompt is acknowledged before it is handed on, so only one that
+    /// arrived right as the connection broke can come again.
+    pub async fn resume(
+        identity: String,
+        server_url: String,
+        last_seq: Option<u64>,
+    ) -> anyhow::Result<Self> {
+        let (queue_tx, queued_rx) = mpsc::unbounded_channel();
+        let session = Arc::new(Session {
+            last_seq: AtomicU64::new(last_seq.unwrap_or(0)),
+            resuming: last_seq.is_some(),
+            queue: Mutex::new(Some(queue_tx)),
+            ..Session::default()
+        });
+
+        let transport = match connect_ws(&identity, &server_url, &session).await {
+            Ok(connection) => connection,
+            // another transport won't make the server like our name any better
+            Err(ws_err) if ws_err.is::<UsernameError>() => return Err(ws_err),
+            Err(ws_err) => {
+                tracing::warn!("{ws_err}, falling back to server sent events");
+
+                match connect_sse(&identity, &server_url, &session).await {
+                    Ok(connection) => connection,
+                    Err(e) if e.is::<UsernameError>() => return Err(e),
+                    Err(e) => return Err(anyhow!("{ws_err}, fallback failed with {e}")),
+                }
             }
         };
 
-        let (sender, receiver) = ws_stream.split();
+        Ok(Self {
+            name: identity,
+            transport,
+            queued_rx: Some(queued_rx),
+            session,
+        })
+    }
 
-        let mut mapped_receiver = receiver.map(|ws_msg| {
-            if let Err(e) = ws_msg {
-                return Err(anyhow!(e));
-            }
+    /// sequence number of the last prompt we got, 0 before the first one. pass
+    /// it to `resume` after a disconnect.
+    pub fn last_seq(&self) -> u64 {
+        self.session.last_seq.load(Ordering::Relaxed)
+    }
 
-            let ws_msg = ws_msg.unwrap();
-            let decoded_msg = ChatMessage::try_from(ws_msg);
+    /// prompts the server never confirmed, e.g. because the connection dropped.
+    /// pass them to `retry_unacknowledged` on the next connection.
+    pub fn unacknowledged(&self) -> Vec<ChatMessage> {
+        self.session.unacknowledged.lock().unwrap().clone()
+    }
 
-            decoded_msg
-        });
+    /// sends the messages again with their original keys, the server drops the
+    /// ones it already got.
+    pub async fn retry_unacknowledged(&mut self, messages: Vec<ChatMessage>) -> anyhow::Result<()> {
+        self.session
+            .unacknowledged
+            .lock()
+            .unwrap()
+            .extend(messages.iter().cloned());
 
-        let (tx, rx) = watch::channel(ChatMessage::new("", "", MessageContent::Close()));
+        for msg in messages {
+            self.send_message(msg).await?;
+        }
 
-        tokio::spawn(async move {
-            loop {
-                let msg = mapped_receiver.next().await;
+        Ok(())
+    }
 
-                if msg.is_none() {
-                    tracing::debug!("got none msg from stream");
-                    break;
-                }
+    pub async fn send_text(&mut self, receiver: String, message: String) -> anyhow::Result<String> {
+        let msg = ChatMessage::new(&self.name, &receiver, MessageContent::Prompt(message));
 
-                let msg = msg.unwrap();
+        self.send_keyed(msg).await
+    }
 
-                if msg.is_err() {
-                    tracing::error!("got error message from stream: {:?}", msg.err().unwrap());
-                    continue;
-                }
+    /// sends the text to all receivers at once. the server answers with a
+    /// `DeliveryReport` saying what happened for each of them.
+    pub async fn multicast_text(
+        &mut self,
+        receivers: Vec<String>,
+        message: String,
+    ) -> anyhow::Result<String> {
+        let msg = ChatMessage::new(&self.name, "", MessageContent::Prompt(message))
+            .with_recipients(receivers);
+
+        self.send_keyed(msg).await
+    }
 
-                if let Err(e) = tx.send(msg.unwrap()) {
-                    tracing::debug!(
-                        "failed to send chat message into internal watch channel: {:?}",
-                        e
-                    );
-                    break;
-                }
-            }
+    /// sends the text to whoever of the on-call group is available. the server
+    /// answers with `OnCallRouted` naming them.
+    pub async fn send_on_call(&mut self, group: String, message: String) -> anyhow::Result<String> {
+        let msg = ChatMessage::new(&self.name, "", MessageContent::Prompt(message))
+            .with_on_call(Some(group));
 
-            // TODO: we might want to close the connection here
-        });
+        self.send_keyed(msg).await
+    }
 
-        Ok(Self {
-            name: identity,
-            client_sink: sender,
-            client_stream_rx: rx,
-        })
+    /// sends a prompt sealed with `crate::e2e::Identity::seal` for the receiver.
+    pub async fn send_sealed(
+        &mut self,
+        receiver: String,
+        sealed: SealedPrompt,
+    ) -> anyhow::Result<String> {
+        let msg = ChatMessage::new(&self.name, &receiver, MessageContent::SealedPrompt(sealed));
+
+        self.send_keyed(msg).await
     }
 
-    pub async fn send_text(&mut self, receiver: String, message: String) -> anyhow::Result<()> {
-        let msg = ChatMessage::new(&self.name, &receiver, MessageContent::Prompt(message));
+    /// same as `send_text` with a priority other than normal. urgent prompts get
+    /// through to users in do-not-disturb mode if the server lets us send them.
+    pub async fn send_text_with_priority(
+        &mut self,
+        receiver: String,
+        message: String,
+        priority: Priority,
+    ) -> anyhow::Result<String> {
+        let msg = ChatMessage::new(&self.name, &receiver, MessageContent::Prompt(message))
+            .with_priority(priority);
+
+        self.send_keyed(msg).await
+    }
+
+    /// same as `send_text` but the prompt is dropped if it can't be shown within
+    /// `ttl`. the sender gets an `Expired` status for it instead.
+    pub async fn send_expiring_text(
+        &mut self,
+        receiver: String,
+        message: String,
+        ttl: Duration,
+    ) -> anyhow::Result<String> {
+        let msg = ChatMessage::new(&self.name, &receiver, MessageContent::Prompt(message))
+            .with_ttl(Some(ttl.as_secs()));
+
+        self.send_keyed(msg).await
+    }
 
-        let wsmsg = msg.try_into().unwrap();
+    pub async fn set_presence(
+        &mut self,
+        status: PresenceStatus,
+        status_text: Option<String>,
+    ) -> anyhow::Result<()> {
+        let presence = Presence {
+            status,
+            status_text,
+        };
 
-        tracing::debug!("sending message: {:?}", &wsmsg);
+        self.send_content(MessageContent::SetPresence(presence))
+            .await
+    }
 
-        self.client_sink.send(wsmsg).await?;
+    /// lets the receiver know we are writing to them.
+    pub async fn send_typing(&mut self, receiver: String) -> anyhow::Result<()> {
+        let msg = ChatMessage::new(&self.name, &receiver, MessageContent::Typing);
 
-        Ok(())
+        self.send_message(msg).await
     }
 
     pub async fn list_users(&mut self) -> anyhow::Result<()> {
         let msg = ChatMessage::new("", "", MessageContent::GetUsersList);
 
-        let wsmsg = msg.try_into().unwrap();
+        self.send_message(msg).await
+    }
+
+    pub async fn block_user(&mut self, user: String) -> anyhow::Result<()> {
+        self.send_content(MessageContent::BlockUser(user)).await
+    }
 
-        tracing::debug!("sending message: {:?}", &wsmsg);
+    pub async fn unblock_user(&mut self, user: String) -> anyhow::Result<()> {
+        self.send_content(MessageContent::UnblockUser(user)).await
+    }
 
-        self.client_sink.send(wsmsg).await?;
+    pub async fn list_blocked_users(&mut self) -> anyhow::Result<()> {
+        self.send_content(MessageContent::GetBlockedUsers).await
+    }
 
-        Ok(())
+    pub async fn mute_user(&mut self, user: String) -> anyhow::Result<()> {
+        self.send_content(MessageContent::MuteUser(user)).await
+    }
+
+    pub async fn unmute_user(&mut self, user: String) -> anyhow::Result<()> {
+        self.send_content(MessageContent::UnmuteUser(user)).await
+    }
+
+    pub async fn list_muted_users(&mut self) -> anyhow::Result<()> {
+        self.send_content(MessageContent::GetMutedUsers).await
+    }
+
+    /// asks the server to deliver the text to the receiver at a later time.
+    pub async fn schedule_text(
+        &mut self,
+        receiver: String,
+        message: String,
+        when: ScheduleTime,
+    ) -> anyhow::Result<String> {
+        let msg = ChatMessage::new(
+            &self.name,
+            &receiver,
+            MessageContent::SchedulePrompt(message, when),
+        );
+
+        self.send_keyed(msg).await
+    }
+
+    pub async fn list_scheduled(&mut self) -> anyhow::Result<()> {
+        self.send_content(MessageContent::GetScheduledPrompts).await
+    }
+
+    pub async fn cancel_scheduled(&mut self, id: u64) -> anyhow::Result<()> {
+        self.send_content(MessageContent::CancelScheduledPrompt(id))
+            .await
+    }
+
+    /// takes back a prompt we sent, `id` is what sending it returned. the server
+    /// echoes the `Recall` once it went through, or answers with an error when
+    /// the recall window is over.
+    pub async fn recall(&mut self, id: String) -> anyhow::Result<()> {
+        self.send_content(MessageContent::Recall(id)).await
+    }
+
+    /// publishes our public key for sealed prompts through the server.
+    pub async fn publish_key(&mut self, key: String) -> anyhow::Result<()> {
+        self.send_content(MessageContent::PublishKey(key)).await
+    }
+
+    /// asks for the user's public key, the server answers with `PublicKey`.
+    pub async fn get_public_key(&mut self, user: String) -> anyhow::Result<()> {
+        self.send_content(MessageContent::GetPublicKey(user)).await
+    }
+
+    /// asks how many prompts we have left today, toward `receiver` too if given.
+    pub async fn get_quota(&mut self, receiver: Option<String>) -> anyhow::Result<()> {
+        let receiver = receiver.unwrap_or_default();
+        let msg = ChatMessage::new(&self.name, &receiver, MessageContent::GetQuota);
+
+        self.send_message(msg).await
+    }
+
+    // prompts carry a fresh idempotency key and are remembered until the server
+    // acks them. the key doubles as the id for recalling the prompt.
+    async fn send_keyed(&mut self, msg: ChatMessage) -> anyhow::Result<String> {
+        let key = uuid::Uuid::new_v4().to_string();
+        let msg = msg.with_key(Some(key.clone()));
+
+        self.session
+            .unacknowledged
+            .lock()
+            .unwrap()
+            .push(msg.clone());
+
+        self.send_message(msg).await?;
+
+        Ok(key)
+    }
+
+    // sends a request that is addressed to the server itself
+    async fn send_content(&mut self, content: MessageContent) -> anyhow::Result<()> {
+        let msg = ChatMessage::new(&self.name, "", content);
+
+        self.send_message(msg).await
     }
 
-    pub fn get_receiver(&self) -> Receiver<ChatMessage> {
-        self.client_stream_rx.clone()
+    /// every message from the server since connecting, in order. there is only
+    /// one, None once it was taken.
+    pub fn take_queued_receiver(&mut self) -> Option<UnboundedReceiver<ChatMessage>> {
+        self.queued_rx.take()
     }
 
     pub async fn close(&mut self) -> anyhow::Result<()> {
-        self.client_sink
-            .send(
-                ChatMessage::new("", "", MessageContent::Close())
-                    .try_into()
-                    .unwrap(),
-            )
-            .await?;
+        match &mut self.transport {
+            Transport::WebSocket(sink) => {
+                sink.lock()
+                    .await
+                    .send(
+                        ChatMessage::new("", "", MessageContent::Close())
+                            .try_into()
+                            .unwrap(),
+                    )
+                    .await?;
+            }
+            // dropping the event stream is what tells the server we're gone
+            Transport::Sse { reader, .. } => reader.abort(),
+        }
+
+        Ok(())
+    }
+
+    async fn send_message(&mut self, msg: ChatMessage) -> anyhow::Result<()> {
+        tracing::debug!("sending message: {:?}", &msg);
+
+        match &mut self.transport {
+            Transport::WebSocket(sink) => {
+                let wsmsg = msg.try_into().unwrap();
+
+                sink.lock().await.send(wsmsg).await?;
+            }
+            Transport::Sse { http, post_url, .. } => {
+                http.post(post_url.as_str())
+                    .json(&msg)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+        }
 
         Ok(())
     }
 }
 
+impl Drop for ChatHandle {
+    fn drop(&mut self) {
+        if let Transport::Sse { reader, .. } = &self.transport {
+            reader.abort();
+        }
+    }
+}
+
+async fn connect_ws(
+    identity: &str,
+    server_url: &str,
+    session: &Arc<Session>,
+) -> anyhow::Result<Transport> {
+    let url = format!("ws://{server_url}/ws/{identity}{}", resume_query(session));
+
+    let ws_stream = match connect_async(url).await {
+        Ok((stream, response)) => {
+            tracing::debug!(
+                "Handshake for client has been completed with {:?}",
+                response
+            );
+
+            stream
+        }
+        Err(tungstenite::Error::Http(response)) => {
+            if let Some(e) = parse_username_error(response.body().as_deref()) {
+                return Err(e.into());
+            }
+
+            // the server says why it refused us in the body, e.g. "server is full"
+            let reason = response
+                .body()
+                .as_deref()
+                .map(String::from_utf8_lossy)
+                .unwrap_or_default();
+
+            return Err(anyhow!(
+                "WebSocket handshake failed with status {}! {reason}",
+                response.status()
+            ));
+        }
+        Err(e) => {
+            return Err(anyhow!("WebSocket handshake failed with {e}!"));
+        }
+    };
+
+    let (sender, receiver) = ws_stream.split();
+
+    // pings are answered by tungstenite itself, they are nothing for us
+    let receiver = receiver.filter(|ws_msg| {
+        let control = matches!(ws_msg, Ok(Message::Ping(_) | Message::Pong(_)));
+
+        futures_util::future::ready(!control)
+    });
+
+    let mut mapped_receiver = receiver.map(|ws_msg| {
+        if let Err(e) = ws_msg {
+            return Err(anyhow!(e));
+        }
+
+        let ws_msg = ws_msg.unwrap();
+
+        ChatMessage::try_from(ws_msg)
+    });
+
+    let sender = Arc::new(tokio::sync::Mutex::new(sender));
+    let session = Arc::clone(session);
+    let receipts = Arc::clone(&sender);
+
+    tokio::spawn(async move {
+        loop {
+            let msg = mapped_receiver.next().await;
+
+            if msg.is_none() {
+                tracing::debug!("got none msg from stream");
+                break;
+            }
+
+            let msg = msg.unwrap();
+
+            if msg.is_err() {
+                tracing::error!("got error message from stream: {:?}", msg.err().unwrap());
+                continue;
+            }
+
+            let msg = msg.unwrap();
+
+            if let Some(receipt) = receipt(&msg) {
+                let receipt: Message = receipt.try_into().unwrap();
+
+                if let Err(e) = receipts.lock().await.send(receipt).await {
+                    tracing::debug!("failed to acknowledge a prompt: {e}");
+                }
+            }
+
+            if !session.received(&msg) {
+                continue;
+            }
+
+            if !session.enqueue(msg) {
+                tracing::debug!("nobody receives messages anymore");
+                break;
+            }
+        }
+
+        // lets the queued receiver know the connection is gone
+        session.queue.lock().unwrap().take();
+
+        // TODO: we might want to close the connection here
+    });
+
+    Ok(Transport::WebSocket(sender))
+}
+
+async fn connect_sse(
+    identity: &str,
+    server_url: &str,
+    session: &Arc<Session>,
+) -> anyhow::Result<Transport> {
+    let http = reqwest::Client::new();
+    let url = format!("http://{server_url}/sse/{identity}");
+
+    let response = http
+        .get(format!("{url}{}", resume_query(session)))
+        .send()
+        .await?;
+
+    if response.status() == reqwest::StatusCode::BAD_REQUEST {
+        let body = response.bytes().await?;
+
+        return match parse_username_error(Some(&body)) {
+            Some(e) => Err(e.into()),
+            None => Err(anyhow!("event stream request was refused")),
+        };
+    }
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let reason = response.text().await.unwrap_or_default();
+
+        return Err(anyhow!(
+            "event stream request failed with status {status}! {reason}"
+        ));
+    }
+
+    let mut events = Box::pin(sse_events(response));
+
+    // the server always starts with the token we need for sending messages
+    let token = match events.next().await {
+        Some(Ok((event, data))) if event == "session" => data,
+        other => return Err(anyhow!("expected a session event, got {:?}", other)),
+    };
+
+    let session = Arc::clone(session);
+    let post_url = format!("{url}?session={token}");
+    let receipts = (http.clone(), post_url.clone());
+
+    let reader = tokio::spawn(async move {
+        while let Some(event) = events.next().await {
+            let msg = event.and_then(|(_, data)| ChatMessage::from_str(&data));
+
+            if let Err(e) = msg {
+                tracing::error!("got error message from event stream: {:?}", e);
+                continue;
+            }
+
+            let msg = msg.unwrap();
+
+            if let Some(receipt) = receipt(&msg) {
+                let (http, post_url) = &receipts;
+
+                if let Err(e) = http.post(post_url.as_str()).json(&receipt).send().await {
+                    tracing::debug!("failed to acknowledge a prompt: {e}");
+                }
+            }
+
+            if !session.received(&msg) {
+                continue;
+            }
+
+            if !session.enqueue(msg) {
+                tracing::debug!("nobody receives messages anymore");
+                break;
+            }
+        }
+
+        tracing::debug!("event stream closed");
+
+        session.queue.lock().unwrap().take();
+    });
+
+    let transport = Transport::Sse {
+        http,
+        post_url,
+        reader,
+    };
+
+    Ok(transport)
+}
+
+// tells the server a numbered message arrived so it isn't replayed after a
+// reconnect. sent before the user sees the message, a close can't overtake it.
+fn receipt(msg: &ChatMessage) -> Option<ChatMessage> {
+    let seq = msg.seq?;
+
+    Some(ChatMessage::new(&msg.to, "", MessageContent::Received(seq)))
+}
+
+fn resume_query(session: &Session) -> String {
+    if !session.resuming {
+        return String::new();
+    }
+
+    format!("?last_seq={}", session.last_seq.load(Ordering::Relaxed))
+}
+
+// the server explains refused usernames in the body of the rejected request
+fn parse_username_error(body: Option<&[u8]>) -> Option<UsernameError> {
+    serde_json::from_slice(body?).ok()
+}
+
+// parses a server sent events body into (event, data) pairs, skipping keep-alive comments
+fn sse_events(response: reqwest::Response) -> impl Stream<Item = anyhow::Result<(String, String)>> {
+    let state = (response.bytes_stream(), Vec::<u8>::new());
+
+    futures_util::stream::unfold(state, |(mut bytes, mut buf)| async move {
+        loop {
+            if let Some(pos) = buf.windows(2).position(|w| w == b"\n\n") {
+                let raw = String::from_utf8_lossy(&buf[..pos]).to_string();
+                buf.drain(..pos + 2);
+
+                let mut event = "message".to_string();
+                let mut data = Vec::new();
+
+                for line in raw.lines() {
+                    if let Some(value) = line.strip_prefix("event:") {
+                        event = value.trim().to_string();
+                    } else if let Some(value) = line.strip_prefix("data:") {
+                        data.push(value.strip_prefix(' ').unwrap_or(value).to_string());
+                    }
+                }
+
+                if data.is_empty() {
+                    continue;
+                }
+
+                return Some((Ok((event, data.join("\n"))), (bytes, buf)));
+            }
+
+            match bytes.next().await {
+                Some(Ok(chunk)) => buf.extend_from_slice(&chunk),
+                Some(Err(e)) => return Some((Err(anyhow!(e)), (bytes, buf))),
+                None => return None,
+            }
+        }
+    })
+}
+
 pub async fn init_client(identity: String, server_url: String) -> anyhow::Result<ChatHandle> {
     let chat_handle = ChatHandle::new(identity, server_url).await?;
 
@@ -0,0 +1,134 @@
+use crate::message::SealedPrompt;
+use anyhow::anyhow;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+// Sealed prompts are encrypted with a key both ends derive from their X25519
+// identity keys, so only the receiver can read them and only the sender could
+// have written them. The sender and receiver names are bound to the ciphertext,
+// the server can't pass a sealed prompt off as coming from someone else.
+
+const KEY_INFO: &[u8] = b"x-ferris-say sealed prompt v1";
+
+/// A client's long lived X25519 key pair.
+pub struct Identity {
+    secret: StaticSecret,
+}
+
+impl Identity {
+    pub fn generate() -> Self {
+        Self {
+            secret: StaticSecret::random_from_rng(OsRng),
+        }
+    }
+
+    /// reads a secret key written by `to_base64`.
+    pub fn from_base64(secret: &str) -> anyhow::Result<Self> {
+        let bytes = decode_key(secret)?;
+
+        Ok(Self {
+            secret: StaticSecret::from(bytes),
+        })
+    }
+
+    pub fn to_base64(&self) -> String {
+        BASE64.encode(self.secret.to_bytes())
+    }
+
+    /// the public half, base64, as published through the server.
+    pub fn public_key(&self) -> String {
+        BASE64.encode(PublicKey::from(&self.secret).as_bytes())
+    }
+
+    /// encrypts `text` from `from` for `to`, whose public key is `peer_key`.
+    pub fn seal(
+        &self,
+        from: &str,
+        to: &str,
+        peer_key: &str,
+        text: &str,
+    ) -> anyhow::Result<SealedPrompt> {
+        let cipher = self.cipher(peer_key)?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let aad = associated_data(from, to);
+
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: text.as_bytes(),
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| anyhow!("failed to seal prompt"))?;
+
+        Ok(SealedPrompt {
+            sender_key: self.public_key(),
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(ciphertext),
+        })
+    }
+
+    /// decrypts a prompt `from` sent to `to`, failing if it was tampered with or
+    /// wasn't sealed by the holder of `sealed.sender_key` for us.
+    pub fn open(&self, from: &str, to: &str, sealed: &SealedPrompt) -> anyhow::Result<String> {
+        let cipher = self.cipher(&sealed.sender_key)?;
+        let nonce = BASE64.decode(&sealed.nonce)?;
+        let ciphertext = BASE64.decode(&sealed.ciphertext)?;
+        let aad = associated_data(from, to);
+
+        if nonce.len() != 12 {
+            return Err(anyhow!("invalid nonce"));
+        }
+
+        let text = cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| anyhow!("failed to open sealed prompt from {from}"))?;
+
+        Ok(String::from_utf8(text)?)
+    }
+
+    fn cipher(&self, peer_key: &str) -> anyhow::Result<ChaCha20Poly1305> {
+        let peer_key = PublicKey::from(decode_key(peer_key)?);
+        let shared = self.secret.diffie_hellman(&peer_key);
+
+        // low order points give a secret anyone could guess
+        if !shared.was_contributory() {
+            return Err(anyhow!("invalid public key"));
+        }
+
+        let mut key = Key::default();
+        Hkdf::<Sha256>::new(None, shared.as_bytes())
+            .expand(KEY_INFO, &mut key)
+            .map_err(|_| anyhow!("failed to derive key"))?;
+
+        Ok(ChaCha20Poly1305::new(&key))
+    }
+}
+
+/// whether `key` looks like a public key, without saying anything about who owns it.
+pub fn is_public_key(key: &str) -> bool {
+    decode_key(key).is_ok()
+}
+
+fn decode_key(key: &str) -> anyhow::Result<[u8; 32]> {
+    BASE64
+        .decode(key)?
+        .try_into()
+        .map_err(|_| anyhow!("keys are 32 bytes long"))
+}
+
+fn associated_data(from: &str, to: &str) -> Vec<u8> {
+    format!("{from}\n{to}").into_bytes()
+}
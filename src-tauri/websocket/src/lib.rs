@@ -1,3 +1,6 @@
 pub mod client;
+pub mod e2e;
 pub mod message;
 pub mod server;
+#[cfg(feature = "testing")]
+pub mod testing;
@@ -1,30 +1,272 @@
 use anyhow::anyhow;
 use axum::extract::ws::Message as AxumMessage;
+use chrono::{DateTime, Utc};
 
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::str::FromStr;
+use std::time::Duration;
 use tokio_tungstenite::tungstenite::Message;
 use MessageContent::Close;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatMessage {
     pub from: String,
     pub to: String,
     pub content: MessageContent,
+    #[serde(default)]
+    pub priority: Priority,
+    /// which picture the receiver should show next to the prompt, e.g. "banana".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub character: Option<String>,
+    /// per receiver sequence number of prompts, set by the server. clients answer
+    /// each with `Received`, what they missed is replayed when they reconnect.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    /// sends a prompt to all of these users instead of `to`. the sender gets a
+    /// `DeliveryReport` back, every receiver gets their own copy with `to` set.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipients: Vec<String>,
+    /// sends a prompt to one available member of this on-call group instead of
+    /// `to`. the sender gets `OnCallRouted` back.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_call: Option<String>,
+    /// idempotency key picked by the sender, e.g. a uuid. the server acts on a key
+    /// only once within its dedupe window and answers every copy with `Ack`, so
+    /// resending after a lost connection can't pop up twice. it also serves as
+    /// the id of a prompt for `Recall`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// a prompt that couldn't be shown within this many seconds is dropped and
+    /// the sender is told it `Expired`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u64>,
+    /// when the prompt expires. the server works it out from `ttl_secs` when the
+    /// prompt arrives, whatever the sender put here.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// set by the server on prompts from senders the receiver muted. clients
+    /// keep them without popping up or taking focus.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub muted: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum MessageContent {
     Close(),
     Prompt(String),
     GetUsersList,
-    ListUsers(Vec<String>),
+    ListUsers(Vec<UserPresence>),
+    SetPresence(Presence),
+    PresenceChanged(UserPresence),
+    BlockUser(String),
+    UnblockUser(String),
+    GetBlockedUsers,
+    BlockedUsers(Vec<String>),
+    /// prompts from muted senders still arrive, marked `muted`.
+    MuteUser(String),
+    UnmuteUser(String),
+    GetMutedUsers,
+    MutedUsers(Vec<String>),
+    /// delivers the prompt to `to` later instead of right away.
+    SchedulePrompt(String, ScheduleTime),
+    GetScheduledPrompts,
+    ScheduledPrompts(Vec<ScheduledPrompt>),
+    CancelScheduledPrompt(u64),
+    /// asks how many prompts the sender has left today. when `to` is set the
+    /// answer also covers prompts to that receiver.
+    GetQuota,
+    Quota(QuotaUsage),
+    /// what happened to a prompt sent to several recipients, per recipient. also
+    /// sent on its own, carrying the prompt's key, for a queued prompt that
+    /// expired before its receiver came back.
+    DeliveryReport(BTreeMap<String, DeliveryStatus>),
+    /// (group, member): who of the on-call group got the prompt.
+    OnCallRouted(String, String),
+    /// the server is done with the message carrying this idempotency key.
+    Ack(String),
+    /// the client got the message with this `seq`, the server needn't replay it.
+    Received(u64),
+    /// takes back the sender's prompt with this key. receivers who already got
+    /// it are sent the same from the sender, the sender gets it back from the
+    /// server once the recall went through.
+    Recall(String),
+    /// a prompt only `to` can read, see `crate::e2e`. it can't go to several
+    /// recipients or an on-call group, and the server's filters don't apply.
+    SealedPrompt(SealedPrompt),
+    /// makes the sender's public key for sealed prompts, base64, known to the server.
+    PublishKey(String),
+    /// asks for the public key of this user.
+    GetPublicKey(String),
+    /// (user, key): the answer to `GetPublicKey`. also sent to everyone online
+    /// when a user publishes a different key.
+    PublicKey(String, Option<String>),
+    /// the sender is writing a prompt for `to`. relayed best effort, never stored.
+    Typing,
     Error(ChatError),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ChatError {
     UserNotOnline,
+    UserDoNotDisturb,
+    /// the server refused the message, e.g. because of a filter, with the reason.
+    Rejected(String),
+    /// the sender used up a daily quota, try again after the reset.
+    QuotaExceeded,
+    /// nobody from the on-call group could take the prompt.
+    NoOneAvailable(String),
+    /// the prompt's ttl ran out before it could be delivered.
+    Expired,
+}
+
+/// Why the server refused a username when connecting. Sent as the json body of
+/// the rejected upgrade request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UsernameError {
+    AlreadyTaken,
+    TooShort(usize),
+    TooLong(usize),
+    InvalidCharacter(char),
+    NotNormalized,
+    MixedScripts,
+    Reserved,
+    ConfusableWith(String),
+}
+
+impl fmt::Display for UsernameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UsernameError::AlreadyTaken => write!(f, "username already exists"),
+            UsernameError::TooShort(min) => {
+                write!(f, "username must be at least {min} characters")
+            }
+            UsernameError::TooLong(max) => {
+                write!(f, "username must be at most {max} characters")
+            }
+            UsernameError::InvalidCharacter(c) => {
+                write!(f, "username can't contain '{}'", c.escape_default())
+            }
+            UsernameError::NotNormalized => {
+                write!(f, "username must be in unicode normalization form C")
+            }
+            UsernameError::MixedScripts => write!(f, "username mixes letters of different scripts"),
+            UsernameError::Reserved => write!(f, "username is reserved"),
+            UsernameError::ConfusableWith(other) => {
+                write!(f, "username looks too much like '{other}'")
+            }
+        }
+    }
+}
+
+impl std::error::Error for UsernameError {}
+
+/// How much a prompt matters. Full replay buffers drop the least important
+/// prompts first, and clients decide how loudly to show them.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    /// gets through do-not-disturb mode, if the sender is allowed to send urgent prompts.
+    Urgent,
+}
+
+/// What happened to a prompt for one of its receivers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Delivered,
+    /// the receiver is reconnecting and gets the prompt once they are back.
+    Queued,
+    Offline,
+    DoNotDisturb,
+    /// only ever audited, the sender is told the prompt was delivered.
+    Blocked,
+    QuotaExceeded,
+    Rejected(String),
+    /// the prompt's ttl ran out before the receiver could get it.
+    Expired,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PresenceStatus {
+    #[default]
+    Available,
+    Away,
+    Busy,
+    DoNotDisturb,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Presence {
+    pub status: PresenceStatus,
+    pub status_text: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserPresence {
+    pub name: String,
+    pub presence: Presence,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScheduleTime {
+    At(DateTime<Utc>),
+    After(Duration),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledPrompt {
+    pub id: u64,
+    pub from: String,
+    pub to: String,
+    pub text: String,
+    pub deliver_at: DateTime<Utc>,
+}
+
+/// A prompt encrypted for its receiver. All fields are base64.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealedPrompt {
+    /// public key of the sender, receivers check it against the one they know.
+    pub sender_key: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+/// Prompts sent today and the daily limits, None when there is no limit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotaUsage {
+    pub sent: u32,
+    pub max_sent: Option<u32>,
+    pub receiver: Option<ReceiverQuota>,
+    /// when the counters start over
+    pub resets_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReceiverQuota {
+    pub name: String,
+    pub sent: u32,
+    pub max_sent: Option<u32>,
+}
+
+impl fmt::Display for PresenceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            PresenceStatus::Available => "available",
+            PresenceStatus::Away => "away",
+            PresenceStatus::Busy => "busy",
+            PresenceStatus::DoNotDisturb => "do not disturb",
+        };
+
+        f.write_str(status)
+    }
 }
 
 impl ChatMessage {
@@ -33,6 +275,94 @@ impl ChatMessage {
             from: from.to_string(),
             to: to.to_string(),
             content,
+            priority: Priority::Normal,
+            character: None,
+            seq: None,
+            recipients: Vec::new(),
+            on_call: None,
+            key: None,
+            ttl_secs: None,
+            expires_at: None,
+            muted: false,
+        }
+    }
+
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_character(mut self, character: Option<String>) -> Self {
+        self.character = character;
+        self
+    }
+
+    pub fn with_recipients(mut self, recipients: Vec<String>) -> Self {
+        self.recipients = recipients;
+        self
+    }
+
+    pub fn with_on_call(mut self, group: Option<String>) -> Self {
+        self.on_call = group;
+        self
+    }
+
+    pub fn with_key(mut self, key: Option<String>) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// sets `ttl_secs` and counts `expires_at` from now. ttls too large for a
+    /// date never expire.
+    pub fn with_ttl(mut self, ttl_secs: Option<u64>) -> Self {
+        self.ttl_secs = ttl_secs;
+        self.expires_at = ttl_secs
+            .and_then(|ttl| i64::try_from(ttl).ok())
+            .and_then(chrono::TimeDelta::try_seconds)
+            .and_then(|ttl| Utc::now().checked_add_signed(ttl));
+        self
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= Utc::now())
+    }
+}
+
+impl MessageContent {
+    /// name of the variant, for logs that should not include the message itself.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MessageContent::Close() => "close",
+            MessageContent::Prompt(_) => "prompt",
+            MessageContent::GetUsersList => "get_users_list",
+            MessageContent::ListUsers(_) => "list_users",
+            MessageContent::SetPresence(_) => "set_presence",
+            MessageContent::PresenceChanged(_) => "presence_changed",
+            MessageContent::BlockUser(_) => "block_user",
+            MessageContent::UnblockUser(_) => "unblock_user",
+            MessageContent::GetBlockedUsers => "get_blocked_users",
+            MessageContent::BlockedUsers(_) => "blocked_users",
+            MessageContent::MuteUser(_) => "mute_user",
+            MessageContent::UnmuteUser(_) => "unmute_user",
+            MessageContent::GetMutedUsers => "get_muted_users",
+            MessageContent::MutedUsers(_) => "muted_users",
+            MessageContent::SchedulePrompt(..) => "schedule_prompt",
+            MessageContent::GetScheduledPrompts => "get_scheduled_prompts",
+            MessageContent::ScheduledPrompts(_) => "scheduled_prompts",
+            MessageContent::CancelScheduledPrompt(_) => "cancel_scheduled_prompt",
+            MessageContent::GetQuota => "get_quota",
+            MessageContent::Quota(_) => "quota",
+            MessageContent::DeliveryReport(_) => "delivery_report",
+            MessageContent::OnCallRouted(..) => "on_call_routed",
+            MessageContent::Ack(_) => "ack",
+            MessageContent::Received(_) => "received",
+            MessageContent::Recall(_) => "recall",
+            MessageContent::SealedPrompt(_) => "sealed_prompt",
+            MessageContent::PublishKey(_) => "publish_key",
+            MessageContent::GetPublicKey(_) => "get_public_key",
+            MessageContent::PublicKey(..) => "public_key",
+            MessageContent::Typing => "typing",
+            MessageContent::Error(_) => "error",
         }
     }
 }
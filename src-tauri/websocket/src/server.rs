@@ -1,102 +1,390 @@
-use crate::message::{ChatError, ChatMessage, MessageContent};
-use axum::extract::ws::Message::Text;
-use axum::extract::{ConnectInfo, Path, State};
+mod admin;
+mod announce;
+mod api;
+mod audit;
+pub mod bot;
+pub mod config;
+mod dedupe;
+pub mod filter;
+mod keys;
+mod limits;
+mod motd;
+mod oncall;
+mod quota;
+mod recall;
+mod replay;
+mod scheduler;
+mod sse;
+mod store;
+mod username;
+pub mod webhook;
+
+use crate::e2e;
+use crate::message::{
+    ChatError, ChatMessage, DeliveryStatus, MessageContent, Presence, PresenceStatus, Priority,
+    ScheduleTime, UserPresence, UsernameError,
+};
+use crate::server::announce::Announcements;
+use crate::server::audit::{AuditEvent, AuditLog};
+use crate::server::bot::Bot;
+use crate::server::config::{ApiToken, ServerConfig};
+use crate::server::dedupe::Dedupe;
+use crate::server::filter::MessageFilter;
+use crate::server::keys::PublicKeys;
+use crate::server::limits::{ConnectionGuard, ConnectionLimits, ConnectionRefused};
+use crate::server::motd::Motd;
+use crate::server::oncall::OnCallGroups;
+use crate::server::quota::Quotas;
+use crate::server::recall::RecallLog;
+use crate::server::replay::ReplayBuffers;
+use crate::server::scheduler::Scheduler;
+use crate::server::store::JsonStore;
+use crate::server::username::UsernameRules;
+use crate::server::webhook::{WebhookEvent, Webhooks};
+use anyhow::anyhow;
+use axum::extract::ws::Message::{Ping, Pong, Text};
+use axum::extract::{ConnectInfo, Path, Query, State};
 use axum::{
     extract::ws::{WebSocket, WebSocketUpgrade},
-    http,
-    response::Response,
-    routing::get,
+    http::{self, HeaderMap},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
     Router,
 };
 use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::select;
-use tokio::sync::mpsc::Sender;
-use tokio::sync::RwLock;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::{Mutex, RwLock};
 
 const SERVRE_IDENTITY: &str = "__SERVER__";
 
-struct Group {
+pub(crate) struct Group {
     user_sinks: RwLock<HashMap<String, Sender<ChatMessage>>>,
+    presences: RwLock<HashMap<String, Presence>>,
+    // blocker -> blocked senders. kept after disconnect so it survives reconnects.
+    blocked_users: RwLock<HashMap<String, HashSet<String>>>,
+    // muter -> muted senders, kept like the blocked ones
+    muted_users: RwLock<HashMap<String, HashSet<String>>>,
+    filters: Vec<Box<dyn MessageFilter>>,
+    scheduler: Scheduler,
+    audit: AuditLog,
+    // user -> session token of users connected through the http fallback
+    sse_sessions: RwLock<HashMap<String, String>>,
+    api_tokens: Vec<ApiToken>,
+    webhooks: Webhooks,
+    usernames: UsernameRules,
+    // (sender, receiver) -> last time a typing indicator was relayed between them
+    typing_relayed: Mutex<HashMap<(String, String), Instant>>,
+    typing_throttle: Duration,
+    ping_interval: Duration,
+    idle_timeout: Duration,
+    replay: ReplayBuffers,
+    quotas: Quotas,
+    connections: Arc<ConnectionLimits>,
+    motd: Motd,
+    announcements: Announcements,
+    max_recipients: usize,
+    on_call: OnCallGroups,
+    dedupe: Dedupe,
+    recalls: RecallLog,
+    urgent_senders: Vec<String>,
+    public_keys: PublicKeys,
+    // names of the registered bots, their replies don't count against quotas
+    bots: RwLock<HashSet<String>>,
 }
 
-pub async fn server_init(port: &str) -> anyhow::Result<()> {
-    let group = Group {
-        user_sinks: RwLock::new(HashMap::new()),
-    };
+/// Builds and runs the chat server. Filters and bots can be added on top of the
+/// ones described in the config.
+pub struct ServerBuilder {
+    port: String,
+    config: ServerConfig,
+    filters: Vec<Box<dyn MessageFilter>>,
+    bots: Vec<Arc<dyn Bot>>,
+}
 
-    let group_state = Arc::new(group);
+impl ServerBuilder {
+    pub fn new(port: &str) -> Self {
+        Self {
+            port: port.to_string(),
+            config: ServerConfig::default(),
+            filters: Vec::new(),
+            bots: Vec::new(),
+        }
+    }
 
-    let app = Router::new()
-        .route("/ws/:user_name", get(handler))
-        .with_state(group_state);
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
 
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port))
-        .await
-        .unwrap();
+    /// adds a filter that runs after the filters from the config.
+    pub fn filter(mut self, filter: impl MessageFilter + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
 
-    tracing::info!("started listening on 0.0.0.0:{}", port);
-    
-    axum::serve::serve(
-        listener,
-        app.into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .await
-    .unwrap();
+    pub fn bot(mut self, bot: impl Bot + 'static) -> Self {
+        self.bots.push(Arc::new(bot));
+        self
+    }
 
-    Ok(())
+    pub async fn serve(self) -> anyhow::Result<()> {
+        let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", self.port)).await?;
+
+        tracing::info!("started listening on 0.0.0.0:{}", self.port);
+
+        self.serve_on(listener).await
+    }
+
+    /// same as `serve` but on a listener the caller already bound, the port is ignored.
+    pub async fn serve_on(self, listener: tokio::net::TcpListener) -> anyhow::Result<()> {
+        let mut filters = filter::filters_from_config(&self.config.filters)?;
+        filters.extend(self.filters);
+
+        let data_dir = self.config.data_dir.as_deref();
+        let scheduler = Scheduler::new(
+            data_dir.map(|dir| JsonStore::new(dir, "scheduled.json")),
+            self.config.scheduler.max_scheduled_per_user,
+        )?;
+
+        // api clients send under their token's name, nobody should be able to pose as them
+        let reserved_names = std::iter::once(SERVRE_IDENTITY.to_string())
+            .chain(self.config.api.tokens.iter().map(|t| t.name.clone()));
+
+        let group = Group {
+            user_sinks: RwLock::new(HashMap::new()),
+            presences: RwLock::new(HashMap::new()),
+            blocked_users: RwLock::new(HashMap::new()),
+            muted_users: RwLock::new(HashMap::new()),
+            filters,
+            scheduler,
+            audit: AuditLog::from_config(&self.config.audit)?,
+            sse_sessions: RwLock::new(HashMap::new()),
+            api_tokens: self.config.api.tokens.clone(),
+            webhooks: Webhooks::from_config(&self.config.webhooks),
+            usernames: UsernameRules::new(self.config.usernames.clone(), reserved_names),
+            typing_relayed: Mutex::new(HashMap::new()),
+            typing_throttle: Duration::from_millis(self.config.typing_throttle_ms),
+            ping_interval: Duration::from_secs(self.config.connections.ping_interval_secs),
+            idle_timeout: Duration::from_secs(self.config.connections.idle_timeout_secs),
+            replay: ReplayBuffers::new(
+                self.config.replay.buffer_size,
+                Duration::from_secs(self.config.replay.resume_window_secs),
+            ),
+            quotas: Quotas::new(
+                data_dir.map(|dir| JsonStore::new(dir, "quotas.json")),
+                self.config.quotas.clone(),
+            )?,
+            connections: ConnectionLimits::new(self.config.connections.clone()),
+            motd: Motd::new(self.config.motd.clone()),
+            announcements: Announcements::new(&self.config.announcements)?,
+            max_recipients: self.config.max_recipients,
+            on_call: OnCallGroups::new(&self.config.on_call),
+            dedupe: Dedupe::new(Duration::from_secs(self.config.dedupe_window_secs)),
+            recalls: RecallLog::new(Duration::from_secs(self.config.recall_window_secs)),
+            urgent_senders: self.config.urgent_senders.clone(),
+            public_keys: PublicKeys::new(
+                data_dir.map(|dir| JsonStore::new(dir, "public_keys.json")),
+            )?,
+            bots: RwLock::new(HashSet::new()),
+        };
+
+        let group_state = Arc::new(group);
+
+        scheduler::spawn_scheduler(Arc::clone(&group_state));
+        announce::spawn_announcer(Arc::clone(&group_state));
+
+        let bots = self.config.bots.iter().map(bot::bot_from_config);
+
+        for bot in bots.chain(self.bots) {
+            // bots go by the same rules as everyone else, and can't share a name
+            let registered = group_state.user_sinks.read().await;
+            if let Err(e) = group_state
+                .usernames
+                .validate(bot.name(), registered.keys())
+            {
+                return Err(anyhow!("invalid bot name {}: {}", bot.name(), e));
+            }
+            drop(registered);
+
+            bot::register_bot(Arc::clone(&group_state), bot).await;
+        }
+
+        let app = Router::new()
+            .route("/ws/:user_name", get(handler))
+            .route(
+                "/sse/:user_name",
+                get(sse::sse_handler).post(sse::post_handler),
+            )
+            .route("/api/messages", post(api::send_handler))
+            .route(
+                "/api/admin/motd",
+                get(admin::get_motd_handler).put(admin::set_motd_handler),
+            )
+            .route(
+                "/api/admin/announcements",
+                get(admin::list_announcements_handler).post(admin::add_announcement_handler),
+            )
+            .route(
+                "/api/admin/announcements/:id",
+                delete(admin::remove_announcement_handler),
+            )
+            .route("/api/admin/on-call", get(admin::list_on_call_handler))
+            .route(
+                "/api/admin/on-call/:name",
+                put(admin::set_on_call_handler).delete(admin::remove_on_call_handler),
+            )
+            .with_state(group_state);
+
+        axum::serve::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+pub async fn server_init(port: &str, config: ServerConfig) -> anyhow::Result<()> {
+    ServerBuilder::new(port).config(config).serve().await
+}
+
+/// Sent by reconnecting clients with the sequence number of the last prompt they got.
+#[derive(Debug, Deserialize)]
+pub(crate) struct ResumeQuery {
+    pub last_seq: Option<u64>,
 }
 
 async fn handler(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Path(user_name): Path<String>,
+    Query(resume): Query<ResumeQuery>,
+    headers: HeaderMap,
     ws: WebSocketUpgrade,
     State(group_state): State<Arc<Group>>,
 ) -> Response {
+    let connection = match admit_connection(&group_state, addr, &headers) {
+        Ok(connection) => connection,
+        Err(e) => return refuse_connection(&group_state, user_name, addr, e),
+    };
+
     // separate block to drop group_state lock
     {
-        let group_state = Arc::clone(&group_state);
         let usernames = group_state.user_sinks.read().await;
-        if usernames.contains_key(&user_name) {
-            return Response::builder()
-                .status(http::StatusCode::BAD_REQUEST)
-                .body("username already exists".into())
-                .unwrap();
+        if let Err(e) = group_state.usernames.validate(&user_name, usernames.keys()) {
+            drop(usernames);
+
+            return reject_username(&group_state, user_name, addr, e);
         }
     }
 
     let username = user_name.clone();
-    let resp = ws.on_upgrade(move |ws| handle_socket(ws, username, group_state));
+    let socket_group_state = Arc::clone(&group_state);
+    let resp = ws.on_upgrade(move |ws| {
+        handle_socket(
+            ws,
+            username,
+            resume.last_seq,
+            socket_group_state,
+            connection,
+        )
+    });
 
     tracing::info!("user {user_name} connected: {}", addr);
 
+    group_state.webhooks.notify(WebhookEvent::UserConnected {
+        user: user_name.clone(),
+    });
+
+    group_state.audit.record(AuditEvent::Connected {
+        user: user_name,
+        addr,
+    });
+
     resp
 }
 
 // connection scenario: after establishing websocket connection
-async fn handle_socket(socket: WebSocket, user_name: String, group_state: Arc<Group>) {
+async fn handle_socket(
+    socket: WebSocket,
+    user_name: String,
+    last_seen: Option<u64>,
+    group_state: Arc<Group>,
+    connection: ConnectionGuard,
+) {
     let (mut sender, mut receiver) = socket.split();
-    let (tx, mut rx) = tokio::sync::mpsc::channel(10);
 
-    let mut sinks = group_state.user_sinks.write().await;
-    sinks.insert(user_name.clone(), tx.clone());
+    let (tx, mut rx) = match join_user(&group_state, &user_name).await {
+        Ok(channel) => channel,
+        Err(e) => {
+            tracing::info!("user {user_name} was refused after the upgrade: {e}");
+            return;
+        }
+    };
+
+    // whatever was missed goes out before live traffic. prompts recorded while we
+    // were collecting the replay can show up in rx as well, those are skipped.
+    let replay = resume_user(&group_state, &user_name, last_seen).await;
+    let replayed = replay.iter().filter_map(|m| m.seq).collect::<HashSet<_>>();
+
+    // resuming clients have seen it already
+    if last_seen.is_none() {
+        send_motd(&group_state, &user_name, &tx).await;
+    }
+
+    for msg in replay {
+        if sender
+            .send(Text(serde_json::to_string(&msg).unwrap()))
+            .await
+            .is_err()
+        {
+            remove_user(&group_state, &user_name).await;
+            return;
+        }
+    }
 
     {
         let tx = tx.clone();
         let group_state_cloned = group_state.clone();
 
         tokio::spawn(async move {
+            // counted as an open connection until this task is done
+            let _connection = connection;
+
+            // half open connections would keep the name taken for good
+            let ping_interval = group_state_cloned.ping_interval;
+            let mut ping = tokio::time::interval_at(
+                tokio::time::Instant::now() + ping_interval,
+                ping_interval,
+            );
+            let mut last_heard = Instant::now();
+
             loop {
                 select! {
+                    _ = ping.tick() => {
+                        let idle = last_heard.elapsed() >= group_state_cloned.idle_timeout;
+
+                        if idle || sender.send(Ping(Vec::new())).await.is_err() {
+                            tracing::info!("user {user_name} stopped answering, disconnecting");
+
+                            remove_user(&group_state_cloned, &user_name).await;
+
+                            break;
+                        }
+                    }
+
                     msg = receiver.next() => {
                         if msg.is_none() {
                             tracing::error!("got none from stream");
 
-                            group_state_cloned.user_sinks.write().await
-                            .remove(&user_name);
+                            remove_user(&group_state_cloned, &user_name).await;
 
                             break;
                         }
@@ -106,13 +394,18 @@ async fn handle_socket(socket: WebSocket, user_name: String, group_state: Arc<Gr
                         if let Err(e) = msg {
                             tracing::error!("found error msg: {:?}", e);
 
-                            group_state_cloned.user_sinks.write().await
-                            .remove(&user_name);
+                            remove_user(&group_state_cloned, &user_name).await;
 
                             break;
                         }
 
                         let msg = msg.unwrap();
+                        last_heard = Instant::now();
+
+                        // axum answers pings, pongs only tell us the client is there
+                        if matches!(msg, Ping(_) | Pong(_)) {
+                            continue;
+                        }
 
                         let chat_message = ChatMessage::try_from(msg);
 
@@ -122,46 +415,25 @@ async fn handle_socket(socket: WebSocket, user_name: String, group_state: Arc<Gr
                         }
                         let chat_message = chat_message.unwrap();
 
-                        let user_sinks = group_state_cloned.user_sinks.read().await;
-
-                        match chat_message.content{
-                            MessageContent::Prompt(_) => {
-                                let target_user_tx = user_sinks.get(&chat_message.to);
-
-                                if target_user_tx.is_none() &&
-                                        tx.send(ChatMessage::new(SERVRE_IDENTITY,
-                                                &user_name,
-                                                MessageContent::Error(ChatError::UserNotOnline))).await.is_err() {
-                                    tracing::info!("client disconnected");
-                                    return;
-                                }
-
-                                    tracing::debug!("sent");
-                                if target_user_tx.unwrap().send(chat_message).await.is_err() {
-                                    tracing::info!("client disconnected");
-                                    return;
-                                }
-                            },
-
-                            MessageContent::GetUsersList => {
-                                let target_user_tx = user_sinks.get(&user_name);
-                                let online_users = list_online_users(group_state_cloned.clone()).await;
-                                let resp = ChatMessage::new(SERVRE_IDENTITY, &user_name, MessageContent::ListUsers(online_users));
-
-                                if target_user_tx.unwrap().send(resp).await.is_err() {
-                                    tracing::info!("client disconnected");
-                                    return;
-                                }
-                            }
+                        if let Err(e) = route_message(&group_state_cloned, &user_name, &tx, chat_message).await {
+                            tracing::info!("client disconnected: {}", e);
 
-                            _ => {}
+                            remove_user(&group_state_cloned, &user_name).await;
+
+                            return;
                         }
                     }
 
                     msg = rx.recv() => {
                         if let Some(msg) = msg {
+                            if msg.seq.is_some_and(|seq| replayed.contains(&seq)) {
+                                continue;
+                            }
+
                             if sender.send(Text(serde_json::to_string(&msg).unwrap())).await.is_err() {
                                 // client disconnected
+                                remove_user(&group_state_cloned, &user_name).await;
+
                                 return;
                             }
                         }
@@ -172,14 +444,929 @@ async fn handle_socket(socket: WebSocket, user_name: String, group_state: Arc<Gr
     }
 }
 
-async fn list_online_users(group_state: Arc<Group>) -> Vec<String> {
+// registers the user as online if the name passes the username rules.
+async fn join_user(
+    group_state: &Group,
+    user_name: &str,
+) -> Result<(Sender<ChatMessage>, Receiver<ChatMessage>), UsernameError> {
+    let mut sinks = group_state.user_sinks.write().await;
+
+    group_state.usernames.validate(user_name, sinks.keys())?;
+
+    let (tx, rx) = tokio::sync::mpsc::channel(10);
+    sinks.insert(user_name.to_string(), tx.clone());
+
+    group_state
+        .presences
+        .write()
+        .await
+        .insert(user_name.to_string(), Presence::default());
+
+    Ok((tx, rx))
+}
+
+// takes the user's replay buffer back and tells the senders of prompts that
+// expired while the user was away.
+pub(crate) async fn resume_user(
+    group_state: &Group,
+    user_name: &str,
+    last_seen: Option<u64>,
+) -> Vec<ChatMessage> {
+    let (replay, expired) = group_state.replay.resume(user_name, last_seen).await;
+
+    for msg in expired {
+        group_state.audit.record(AuditEvent::MessageRejected {
+            from: msg.from.clone(),
+            to: msg.to.clone(),
+            reason: format!("{:?}", DeliveryStatus::Expired),
+        });
+
+        let sender_tx = group_state.user_sinks.read().await.get(&msg.from).cloned();
+
+        if let Some(sender_tx) = sender_tx {
+            let report = ChatMessage::new(
+                SERVRE_IDENTITY,
+                &msg.from,
+                MessageContent::DeliveryReport(BTreeMap::from([(
+                    msg.to.clone(),
+                    DeliveryStatus::Expired,
+                )])),
+            )
+            .with_key(msg.key);
+
+            let _ = sender_tx.send(report).await;
+        }
+    }
+
+    replay
+}
+
+async fn send_motd(group_state: &Group, user_name: &str, tx: &Sender<ChatMessage>) {
+    let online = group_state.user_sinks.read().await.len();
+
+    let Some(motd) = group_state.motd.render(user_name, online).await else {
+        return;
+    };
+
+    let msg = ChatMessage::new(SERVRE_IDENTITY, user_name, MessageContent::Prompt(motd));
+
+    if tx.send(msg).await.is_err() {
+        tracing::debug!("{user_name} left before getting the motd");
+    }
+}
+
+// checks the origin and the connection limits before anything else about the request
+pub(crate) fn admit_connection(
+    group_state: &Group,
+    addr: SocketAddr,
+    headers: &HeaderMap,
+) -> Result<ConnectionGuard, ConnectionRefused> {
+    group_state.connections.check_origin(headers)?;
+    group_state.connections.acquire(addr.ip())
+}
+
+pub(crate) fn refuse_connection(
+    group_state: &Group,
+    user_name: String,
+    addr: SocketAddr,
+    refused: ConnectionRefused,
+) -> Response {
+    tracing::info!("refused connection of {user_name} from {addr}: {refused}");
+
+    group_state.audit.record(AuditEvent::ConnectionRejected {
+        user: user_name,
+        addr,
+        reason: refused.to_string(),
+    });
+
+    (refused.status(), refused.to_string()).into_response()
+}
+
+// answers a connection attempt with the reason its username was refused
+fn reject_username(
+    group_state: &Group,
+    user_name: String,
+    addr: SocketAddr,
+    error: UsernameError,
+) -> Response {
+    group_state.audit.record(AuditEvent::ConnectionRejected {
+        user: user_name,
+        addr,
+        reason: error.to_string(),
+    });
+
+    Response::builder()
+        .status(http::StatusCode::BAD_REQUEST)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(serde_json::to_string(&error).unwrap().into())
+        .unwrap()
+}
+
+// handles a single message received from user_name's socket. an error means the
+// user's own sink is gone and the connection should be dropped.
+pub(crate) async fn route_message(
+    group_state: &Group,
+    user_name: &str,
+    tx: &Sender<ChatMessage>,
+    chat_message: ChatMessage,
+) -> anyhow::Result<()> {
+    // receivers get the key too, it is how a recall names the prompt
+    let Some(key) = chat_message.key.clone() else {
+        return handle_message(group_state, user_name, tx, chat_message).await;
+    };
+
+    if group_state.dedupe.first_seen(user_name, &key).await {
+        handle_message(group_state, user_name, tx, chat_message).await?;
+    } else {
+        tracing::debug!("dropping duplicate of {key} from {user_name}");
+    }
+
+    let ack = ChatMessage::new(SERVRE_IDENTITY, user_name, MessageContent::Ack(key));
+
+    tx.send(ack).await.map_err(|e| anyhow!(e.to_string()))
+}
+
+async fn handle_message(
+    group_state: &Group,
+    user_name: &str,
+    tx: &Sender<ChatMessage>,
+    mut chat_message: ChatMessage,
+) -> anyhow::Result<()> {
+    // never trust the sender field provided by clients, nor their clocks
+    chat_message.from = user_name.to_string();
+    let ttl_secs = chat_message.ttl_secs;
+    chat_message = chat_message.with_ttl(ttl_secs);
+
+    // typing indicators and receipts are too chatty and too meaningless to audit,
+    // multicast and on-call prompts are audited once their receivers are known
+    if !matches!(
+        chat_message.content,
+        MessageContent::Typing | MessageContent::Received(_)
+    ) && chat_message.recipients.is_empty()
+        && chat_message.on_call.is_none()
+    {
+        group_state.audit.record(AuditEvent::Message {
+            from: user_name.to_string(),
+            to: chat_message.to.clone(),
+            kind: chat_message.content.kind(),
+            body: match &chat_message.content {
+                MessageContent::Prompt(text) | MessageContent::SchedulePrompt(text, _) => {
+                    Some(text.clone())
+                }
+                _ => None,
+            },
+        });
+    }
+
+    match chat_message.content {
+        MessageContent::Typing => relay_typing(group_state, chat_message).await,
+
+        MessageContent::Received(seq) => group_state.replay.acknowledge(user_name, seq).await,
+
+        MessageContent::Prompt(_) if chat_message.on_call.is_some() => {
+            let group = chat_message.on_call.clone().unwrap_or_default();
+
+            let member = match deliver_on_call(group_state, chat_message).await {
+                Ok(member) => member,
+                Err(error) => return send_error(user_name, tx, error).await,
+            };
+
+            let resp = ChatMessage::new(
+                SERVRE_IDENTITY,
+                user_name,
+                MessageContent::OnCallRouted(group, member),
+            );
+
+            tx.send(resp).await.map_err(|e| anyhow!(e.to_string()))?;
+        }
+
+        MessageContent::Prompt(_) if !chat_message.recipients.is_empty() => {
+            return multicast_prompt(group_state, user_name, tx, chat_message).await;
+        }
+
+        MessageContent::SealedPrompt(_)
+            if !chat_message.recipients.is_empty() || chat_message.on_call.is_some() =>
+        {
+            let reason = "sealed prompts can only go to a single receiver".to_string();
+
+            return reject(
+                group_state,
+                user_name,
+                &chat_message.to,
+                tx,
+                ChatError::Rejected(reason),
+            )
+            .await;
+        }
+
+        MessageContent::Prompt(_) | MessageContent::SealedPrompt(_) => {
+            let error = match deliver_prompt(group_state, chat_message).await {
+                DeliveryStatus::Delivered | DeliveryStatus::Queued | DeliveryStatus::Blocked => {
+                    return Ok(())
+                }
+                DeliveryStatus::Offline => ChatError::UserNotOnline,
+                DeliveryStatus::DoNotDisturb => ChatError::UserDoNotDisturb,
+                DeliveryStatus::QuotaExceeded => ChatError::QuotaExceeded,
+                DeliveryStatus::Rejected(reason) => ChatError::Rejected(reason),
+                DeliveryStatus::Expired => ChatError::Expired,
+            };
+
+            return send_error(user_name, tx, error).await;
+        }
+
+        MessageContent::GetUsersList => {
+            let online_users = list_online_users(group_state).await;
+            let resp = ChatMessage::new(
+                SERVRE_IDENTITY,
+                user_name,
+                MessageContent::ListUsers(online_users),
+            );
+
+            tx.send(resp).await.map_err(|e| anyhow!(e.to_string()))?;
+        }
+
+        MessageContent::SetPresence(presence) => {
+            group_state
+                .presences
+                .write()
+                .await
+                .insert(user_name.to_string(), presence.clone());
+
+            let update = UserPresence {
+                name: user_name.to_string(),
+                presence,
+            };
+
+            broadcast(
+                group_state,
+                user_name,
+                MessageContent::PresenceChanged(update),
+            )
+            .await;
+        }
+
+        MessageContent::BlockUser(blocked) => {
+            group_state
+                .blocked_users
+                .write()
+                .await
+                .entry(user_name.to_string())
+                .or_default()
+                .insert(blocked);
+
+            send_blocked_users(group_state, user_name, tx).await?;
+        }
+
+        MessageContent::UnblockUser(blocked) => {
+            if let Some(list) = group_state.blocked_users.write().await.get_mut(user_name) {
+                list.remove(&blocked);
+            }
+
+            send_blocked_users(group_state, user_name, tx).await?;
+        }
+
+        MessageContent::GetBlockedUsers => {
+            send_blocked_users(group_state, user_name, tx).await?;
+        }
+
+        MessageContent::MuteUser(muted) => {
+            group_state
+                .muted_users
+                .write()
+                .await
+                .entry(user_name.to_string())
+                .or_default()
+                .insert(muted);
+
+            send_muted_users(group_state, user_name, tx).await?;
+        }
+
+        MessageContent::UnmuteUser(muted) => {
+            if let Some(list) = group_state.muted_users.write().await.get_mut(user_name) {
+                list.remove(&muted);
+            }
+
+            send_muted_users(group_state, user_name, tx).await?;
+        }
+
+        MessageContent::GetMutedUsers => {
+            send_muted_users(group_state, user_name, tx).await?;
+        }
+
+        MessageContent::SchedulePrompt(text, when) => {
+            let deliver_at = match when {
+                ScheduleTime::At(at) => Some(at),
+                ScheduleTime::After(delay) => scheduler::deliver_at_after(delay),
+            };
+
+            let Some(deliver_at) = deliver_at else {
+                return reject(
+                    group_state,
+                    user_name,
+                    &chat_message.to,
+                    tx,
+                    ChatError::Rejected("that is too far in the future".to_string()),
+                )
+                .await;
+            };
+
+            let scheduled = group_state
+                .scheduler
+                .schedule(user_name, &chat_message.to, text, deliver_at)
+                .await;
+
+            if let Err(reason) = scheduled {
+                return reject(
+                    group_state,
+                    user_name,
+                    &chat_message.to,
+                    tx,
+                    ChatError::Rejected(reason),
+                )
+                .await;
+            }
+
+            send_scheduled_prompts(group_state, user_name, tx).await?;
+        }
+
+        MessageContent::CancelScheduledPrompt(id) => {
+            group_state.scheduler.cancel(user_name, id).await;
+
+            send_scheduled_prompts(group_state, user_name, tx).await?;
+        }
+
+        MessageContent::GetScheduledPrompts => {
+            send_scheduled_prompts(group_state, user_name, tx).await?;
+        }
+
+        MessageContent::Recall(key) => {
+            return recall_prompt(group_state, user_name, tx, key).await;
+        }
+
+        MessageContent::PublishKey(key) => {
+            if !e2e::is_public_key(&key) {
+                let reason = "not a valid public key".to_string();
+
+                return reject(group_state, user_name, "", tx, ChatError::Rejected(reason)).await;
+            }
+
+            if group_state
+                .public_keys
+                .publish(user_name, key.clone())
+                .await
+            {
+                tracing::info!("user {user_name} published a new public key");
+
+                broadcast(
+                    group_state,
+                    user_name,
+                    MessageContent::PublicKey(user_name.to_string(), Some(key)),
+                )
+                .await;
+            }
+        }
+
+        MessageContent::GetPublicKey(user) => {
+            let key = group_state.public_keys.get(&user).await;
+            let resp = ChatMessage::new(
+                SERVRE_IDENTITY,
+                user_name,
+                MessageContent::PublicKey(user, key),
+            );
+
+            tx.send(resp).await.map_err(|e| anyhow!(e.to_string()))?;
+        }
+
+        MessageContent::GetQuota => {
+            let receiver = Some(chat_message.to.as_str()).filter(|to| !to.is_empty());
+            let usage = group_state.quotas.usage(user_name, receiver).await;
+            let resp = ChatMessage::new(SERVRE_IDENTITY, user_name, MessageContent::Quota(usage));
+
+            tx.send(resp).await.map_err(|e| anyhow!(e.to_string()))?;
+        }
+
+        _ => {}
+    }
+
+    Ok(())
+}
+
+// takes the prompt back from everyone it went to. prompts still waiting for a
+// reconnecting receiver are dropped, the others are recalled on the receiver's side.
+async fn recall_prompt(
+    group_state: &Group,
+    user_name: &str,
+    tx: &Sender<ChatMessage>,
+    key: String,
+) -> anyhow::Result<()> {
+    let Some(receivers) = group_state.recalls.take(user_name, &key).await else {
+        let reason = "this prompt can't be recalled anymore".to_string();
+
+        return reject(group_state, user_name, "", tx, ChatError::Rejected(reason)).await;
+    };
+
+    for receiver in receivers {
+        if group_state.replay.remove(&receiver, user_name, &key).await {
+            continue;
+        }
+
+        let sink = group_state.user_sinks.read().await.get(&receiver).cloned();
+
+        if sink.is_none() && !group_state.replay.is_resumable(&receiver).await {
+            continue;
+        }
+
+        // numbered like prompts so a receiver who is reconnecting still gets it
+        let mut recall =
+            ChatMessage::new(user_name, &receiver, MessageContent::Recall(key.clone()));
+        group_state.replay.record(&mut recall).await;
+
+        if let Some(sink) = sink {
+            let _ = sink.send(recall).await;
+        }
+    }
+
+    let resp = ChatMessage::new(SERVRE_IDENTITY, user_name, MessageContent::Recall(key));
+
+    tx.send(resp).await.map_err(|e| anyhow!(e.to_string()))
+}
+
+// delivers a copy of the prompt to every recipient and tells the sender how each went
+async fn multicast_prompt(
+    group_state: &Group,
+    user_name: &str,
+    tx: &Sender<ChatMessage>,
+    mut chat_message: ChatMessage,
+) -> anyhow::Result<()> {
+    let mut recipients = std::mem::take(&mut chat_message.recipients);
+    recipients.sort();
+    recipients.dedup();
+
+    if recipients.len() > group_state.max_recipients {
+        let reason = format!(
+            "you can't send to more than {} users at once",
+            group_state.max_recipients
+        );
+
+        return reject(
+            group_state,
+            user_name,
+            &chat_message.to,
+            tx,
+            ChatError::Rejected(reason),
+        )
+        .await;
+    }
+
+    let mut results = BTreeMap::new();
+
+    for receiver in recipients {
+        let mut msg = chat_message.clone();
+        msg.to = receiver.clone();
+
+        audit_prompt(group_state, &msg);
+
+        results.insert(receiver, deliver_prompt(group_state, msg).await);
+    }
+
+    // keyed like the prompt, so the sender knows which one it is about
+    let report = ChatMessage::new(
+        SERVRE_IDENTITY,
+        user_name,
+        MessageContent::DeliveryReport(results),
+    )
+    .with_key(chat_message.key);
+
+    tx.send(report).await.map_err(|e| anyhow!(e.to_string()))
+}
+
+// hands the prompt to the first member of the on-call group, in the order of the
+// group's strategy, who is online and willing to take it. returns who got it.
+pub(crate) async fn deliver_on_call(
+    group_state: &Group,
+    mut chat_message: ChatMessage,
+) -> Result<String, ChatError> {
+    let group = chat_message.on_call.take().unwrap_or_default();
+
+    let Some(candidates) = group_state.on_call.candidates(&group).await else {
+        return Err(ChatError::Rejected(format!(
+            "unknown on-call group {group}"
+        )));
+    };
+
+    for member in candidates {
+        if !is_available(group_state, &member, &chat_message).await {
+            continue;
+        }
+
+        let mut msg = chat_message.clone();
+        msg.to = member.clone();
+
+        audit_prompt(group_state, &msg);
+
+        match deliver_prompt(group_state, msg).await {
+            DeliveryStatus::Delivered => {
+                group_state.on_call.pinged(&group, &member).await;
+
+                return Ok(member);
+            }
+            // a filter would turn it down for anyone else just the same
+            DeliveryStatus::Rejected(reason) => return Err(ChatError::Rejected(reason)),
+            _ => {}
+        }
+    }
+
+    Err(ChatError::NoOneAvailable(group))
+}
+
+// online, not blocking or muting the sender and not in do-not-disturb mode,
+// unless the prompt is urgent and its sender may break through that
+async fn is_available(group_state: &Group, user_name: &str, chat_message: &ChatMessage) -> bool {
+    if !group_state.user_sinks.read().await.contains_key(user_name) {
+        return false;
+    }
+
+    let do_not_disturb = group_state
+        .presences
+        .read()
+        .await
+        .get(user_name)
+        .is_some_and(|p| p.status == PresenceStatus::DoNotDisturb);
+
+    let urgent = chat_message.priority == Priority::Urgent
+        && may_send_urgent(group_state, &chat_message.from);
+
+    (!do_not_disturb || urgent)
+        && !is_blocked(group_state, user_name, &chat_message.from).await
+        && !is_muted(group_state, user_name, &chat_message.from).await
+}
+
+pub(crate) fn audit_prompt(group_state: &Group, chat_message: &ChatMessage) {
+    group_state.audit.record(AuditEvent::Message {
+        from: chat_message.from.clone(),
+        to: chat_message.to.clone(),
+        kind: chat_message.content.kind(),
+        body: match &chat_message.content {
+            MessageContent::Prompt(text) => Some(text.clone()),
+            _ => None,
+        },
+    });
+}
+
+// runs a prompt through the filters and checks and hands it to the receiver.
+// every outcome other than delivery is audited here. blocked senders are not
+// told about it, prompts to someone blocking them are reported as delivered.
+pub(crate) async fn deliver_prompt(
+    group_state: &Group,
+    mut chat_message: ChatMessage,
+) -> DeliveryStatus {
+    let status = try_deliver_prompt(group_state, &mut chat_message).await;
+
+    if status == DeliveryStatus::Offline {
+        group_state
+            .webhooks
+            .notify(WebhookEvent::MessageUndeliverable {
+                from: chat_message.from.clone(),
+                to: chat_message.to.clone(),
+            });
+    }
+
+    if matches!(status, DeliveryStatus::Delivered | DeliveryStatus::Queued) {
+        if let Some(key) = &chat_message.key {
+            group_state
+                .recalls
+                .record(&chat_message.from, key, &chat_message.to)
+                .await;
+        }
+    } else {
+        group_state.audit.record(AuditEvent::MessageRejected {
+            from: chat_message.from,
+            to: chat_message.to,
+            reason: format!("{:?}", status),
+        });
+    }
+
+    match status {
+        DeliveryStatus::Blocked => DeliveryStatus::Delivered,
+        status => status,
+    }
+}
+
+async fn try_deliver_prompt(group_state: &Group, chat_message: &mut ChatMessage) -> DeliveryStatus {
+    if chat_message.is_expired() {
+        return DeliveryStatus::Expired;
+    }
+
+    if chat_message.priority == Priority::Urgent
+        && !may_send_urgent(group_state, &chat_message.from)
+    {
+        chat_message.priority = Priority::Normal;
+    }
+
+    if let MessageContent::Prompt(text) = &mut chat_message.content {
+        match filter::apply_filters(
+            &group_state.filters,
+            &chat_message.from,
+            &chat_message.to,
+            text.clone(),
+        ) {
+            Ok(filtered) if filtered == *text => {}
+            Ok(filtered) => {
+                group_state.webhooks.notify(WebhookEvent::MessageFiltered {
+                    from: chat_message.from.clone(),
+                    to: chat_message.to.clone(),
+                    reason: None,
+                });
+
+                *text = filtered;
+            }
+            Err(reason) => {
+                group_state.webhooks.notify(WebhookEvent::MessageFiltered {
+                    from: chat_message.from.clone(),
+                    to: chat_message.to.clone(),
+                    reason: Some(reason.clone()),
+                });
+
+                return DeliveryStatus::Rejected(reason);
+            }
+        }
+    }
+
+    if is_blocked(group_state, &chat_message.to, &chat_message.from).await {
+        tracing::debug!(
+            "dropping prompt from {} blocked by {}",
+            chat_message.from,
+            chat_message.to
+        );
+
+        return DeliveryStatus::Blocked;
+    }
+
+    chat_message.muted = is_muted(group_state, &chat_message.to, &chat_message.from).await;
+
+    let target_user_tx = group_state
+        .user_sinks
+        .read()
+        .await
+        .get(&chat_message.to)
+        .cloned();
+
+    let Some(target_user_tx) = target_user_tx else {
+        if group_state.replay.is_resumable(&chat_message.to).await {
+            if !consume_quota(group_state, chat_message).await {
+                return DeliveryStatus::QuotaExceeded;
+            }
+
+            group_state.replay.record(chat_message).await;
+
+            return DeliveryStatus::Queued;
+        }
+
+        return DeliveryStatus::Offline;
+    };
+
+    let target_presence = group_state
+        .presences
+        .read()
+        .await
+        .get(&chat_message.to)
+        .cloned()
+        .unwrap_or_default();
+
+    if target_presence.status == PresenceStatus::DoNotDisturb
+        && chat_message.priority != Priority::Urgent
+    {
+        return DeliveryStatus::DoNotDisturb;
+    }
+
+    if !consume_quota(group_state, chat_message).await {
+        return DeliveryStatus::QuotaExceeded;
+    }
+
+    group_state.replay.record(chat_message).await;
+
+    if target_user_tx.send(chat_message.clone()).await.is_err() {
+        tracing::debug!("receiver disconnected before delivery");
+
+        return DeliveryStatus::Offline;
+    }
+
+    DeliveryStatus::Delivered
+}
+
+fn may_send_urgent(group_state: &Group, sender: &str) -> bool {
+    sender == SERVRE_IDENTITY
+        || group_state
+            .urgent_senders
+            .iter()
+            .any(|allowed| allowed == "*" || allowed == sender)
+}
+
+// the server's own announcements don't count against anyone's quota
+async fn consume_quota(group_state: &Group, chat_message: &ChatMessage) -> bool {
+    if chat_message.from == SERVRE_IDENTITY
+        || group_state.bots.read().await.contains(&chat_message.from)
+    {
+        return true;
+    }
+
+    group_state
+        .quotas
+        .consume(&chat_message.from, &chat_message.to)
+        .await
+}
+
+// best effort: throttled per sender and receiver, dropped when the receiver is
+// offline, has blocked or muted the sender or has a full inbox.
+async fn relay_typing(group_state: &Group, chat_message: ChatMessage) {
+    {
+        let key = (chat_message.from.clone(), chat_message.to.clone());
+        let now = Instant::now();
+        let mut last_relayed = group_state.typing_relayed.lock().await;
+
+        if last_relayed
+            .get(&key)
+            .is_some_and(|last| now.duration_since(*last) < group_state.typing_throttle)
+        {
+            return;
+        }
+
+        last_relayed.retain(|_, last| now.duration_since(*last) < group_state.typing_throttle);
+        last_relayed.insert(key, now);
+    }
+
+    if is_blocked(group_state, &chat_message.to, &chat_message.from).await
+        || is_muted(group_state, &chat_message.to, &chat_message.from).await
+    {
+        return;
+    }
+
+    let target_user_tx = group_state
+        .user_sinks
+        .read()
+        .await
+        .get(&chat_message.to)
+        .cloned();
+
+    if let Some(target_user_tx) = target_user_tx {
+        let _ = target_user_tx.try_send(chat_message);
+    }
+}
+
+// tells the sender why their message went nowhere
+async fn reject(
+    group_state: &Group,
+    user_name: &str,
+    to: &str,
+    tx: &Sender<ChatMessage>,
+    error: ChatError,
+) -> anyhow::Result<()> {
+    group_state.audit.record(AuditEvent::MessageRejected {
+        from: user_name.to_string(),
+        to: to.to_string(),
+        reason: format!("{:?}", error),
+    });
+
+    send_error(user_name, tx, error).await
+}
+
+async fn send_error(
+    user_name: &str,
+    tx: &Sender<ChatMessage>,
+    error: ChatError,
+) -> anyhow::Result<()> {
+    let resp = ChatMessage::new(SERVRE_IDENTITY, user_name, MessageContent::Error(error));
+
+    tx.send(resp).await.map_err(|e| anyhow!(e.to_string()))
+}
+
+async fn is_blocked(group_state: &Group, receiver: &str, sender: &str) -> bool {
+    group_state
+        .blocked_users
+        .read()
+        .await
+        .get(receiver)
+        .is_some_and(|list| list.contains(sender))
+}
+
+async fn is_muted(group_state: &Group, receiver: &str, sender: &str) -> bool {
+    group_state
+        .muted_users
+        .read()
+        .await
+        .get(receiver)
+        .is_some_and(|list| list.contains(sender))
+}
+
+async fn send_blocked_users(
+    group_state: &Group,
+    user_name: &str,
+    tx: &Sender<ChatMessage>,
+) -> anyhow::Result<()> {
+    let blocked = group_state
+        .blocked_users
+        .read()
+        .await
+        .get(user_name)
+        .map(|list| list.iter().cloned().collect())
+        .unwrap_or_default();
+
+    let resp = ChatMessage::new(
+        SERVRE_IDENTITY,
+        user_name,
+        MessageContent::BlockedUsers(blocked),
+    );
+
+    tx.send(resp).await.map_err(|e| anyhow!(e.to_string()))
+}
+
+async fn send_muted_users(
+    group_state: &Group,
+    user_name: &str,
+    tx: &Sender<ChatMessage>,
+) -> anyhow::Result<()> {
+    let muted = group_state
+        .muted_users
+        .read()
+        .await
+        .get(user_name)
+        .map(|list| list.iter().cloned().collect())
+        .unwrap_or_default();
+
+    let resp = ChatMessage::new(
+        SERVRE_IDENTITY,
+        user_name,
+        MessageContent::MutedUsers(muted),
+    );
+
+    tx.send(resp).await.map_err(|e| anyhow!(e.to_string()))
+}
+
+// sends content from the server to every online user except `except`
+async fn broadcast(group_state: &Group, except: &str, content: MessageContent) {
+    let sinks = group_state
+        .user_sinks
+        .read()
+        .await
+        .iter()
+        .filter(|(name, _)| name.as_str() != except)
+        .map(|(name, sink)| (name.clone(), sink.clone()))
+        .collect::<Vec<_>>();
+
+    for (name, sink) in sinks {
+        let msg = ChatMessage::new(SERVRE_IDENTITY, &name, content.clone());
+
+        if sink.send(msg).await.is_err() {
+            tracing::debug!("failed to broadcast to {name}");
+        }
+    }
+}
+
+async fn send_scheduled_prompts(
+    group_state: &Group,
+    user_name: &str,
+    tx: &Sender<ChatMessage>,
+) -> anyhow::Result<()> {
+    let scheduled = group_state.scheduler.list(user_name).await;
+
+    let resp = ChatMessage::new(
+        SERVRE_IDENTITY,
+        user_name,
+        MessageContent::ScheduledPrompts(scheduled),
+    );
+
+    tx.send(resp).await.map_err(|e| anyhow!(e.to_string()))
+}
+
+async fn remove_user(group_state: &Group, user_name: &str) {
+    group_state.webhooks.notify(WebhookEvent::UserDisconnected {
+        user: user_name.to_string(),
+    });
+
+    group_state.audit.record(AuditEvent::Disconnected {
+        user: user_name.to_string(),
+    });
+
+    // marked first, so prompts sent from here on are kept for a reconnect
+    group_state.replay.disconnected(user_name).await;
+    group_state.user_sinks.write().await.remove(user_name);
+    group_state.sse_sessions.write().await.remove(user_name);
+    group_state.presences.write().await.remove(user_name);
+}
+
+async fn list_online_users(group_state: &Group) -> Vec<UserPresence> {
     let users = group_state.user_sinks.read().await;
-    let mut result = Vec::with_capacity(users.capacity());
+    let presences = group_state.presences.read().await;
+    let mut result = Vec::with_capacity(users.len());
 
     for user in users.keys() {
-        result.push(user.clone());
+        result.push(UserPresence {
+            name: user.clone(),
+            presence: presences.get(user).cloned().unwrap_or_default(),
+        });
     }
 
+    drop(presences);
     drop(users);
 
     result
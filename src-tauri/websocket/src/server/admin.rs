@@ -0,0 +1,154 @@
+use crate::server::api::{authenticate, unauthorized};
+use crate::server::config::{AnnouncementConfig, OnCallGroupConfig, RoutingStrategy};
+use crate::server::Group;
+use axum::extract::{Path, State};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use axum::{http, Json};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// Endpoints for changing the server while it runs. Only tokens marked as
+// `admin` in the `api` section of the server config may use them.
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct MotdBody {
+    /// None or an empty string turns the motd off.
+    motd: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct OnCallBody {
+    members: Vec<String>,
+    #[serde(default)]
+    strategy: RoutingStrategy,
+}
+
+pub(crate) async fn get_motd_handler(
+    State(group_state): State<Arc<Group>>,
+    headers: HeaderMap,
+) -> Response {
+    if !authenticate(&group_state, &headers).is_some_and(|t| t.admin) {
+        return unauthorized();
+    }
+
+    let motd = group_state.motd.get().await;
+
+    Json(MotdBody { motd }).into_response()
+}
+
+pub(crate) async fn set_motd_handler(
+    State(group_state): State<Arc<Group>>,
+    headers: HeaderMap,
+    Json(body): Json<MotdBody>,
+) -> Response {
+    let Some(admin) = authenticate(&group_state, &headers).filter(|t| t.admin) else {
+        return unauthorized();
+    };
+
+    tracing::info!("{} changed the motd to {:?}", admin.name, body.motd);
+
+    group_state.motd.set(body.motd.clone()).await;
+
+    Json(body).into_response()
+}
+
+pub(crate) async fn list_announcements_handler(
+    State(group_state): State<Arc<Group>>,
+    headers: HeaderMap,
+) -> Response {
+    if !authenticate(&group_state, &headers).is_some_and(|t| t.admin) {
+        return unauthorized();
+    }
+
+    Json(group_state.announcements.list().await).into_response()
+}
+
+pub(crate) async fn add_announcement_handler(
+    State(group_state): State<Arc<Group>>,
+    headers: HeaderMap,
+    Json(config): Json<AnnouncementConfig>,
+) -> Response {
+    let Some(admin) = authenticate(&group_state, &headers).filter(|t| t.admin) else {
+        return unauthorized();
+    };
+
+    match group_state.announcements.add(config).await {
+        Ok(announcement) => {
+            tracing::info!("{} added announcement {:?}", admin.name, announcement);
+
+            (http::StatusCode::CREATED, Json(announcement)).into_response()
+        }
+        Err(e) => (http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+pub(crate) async fn remove_announcement_handler(
+    State(group_state): State<Arc<Group>>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+) -> Response {
+    let Some(admin) = authenticate(&group_state, &headers).filter(|t| t.admin) else {
+        return unauthorized();
+    };
+
+    if !group_state.announcements.remove(id).await {
+        return (http::StatusCode::NOT_FOUND, "no such announcement").into_response();
+    }
+
+    tracing::info!("{} removed announcement {id}", admin.name);
+
+    http::StatusCode::NO_CONTENT.into_response()
+}
+
+pub(crate) async fn list_on_call_handler(
+    State(group_state): State<Arc<Group>>,
+    headers: HeaderMap,
+) -> Response {
+    if !authenticate(&group_state, &headers).is_some_and(|t| t.admin) {
+        return unauthorized();
+    }
+
+    Json(group_state.on_call.list().await).into_response()
+}
+
+pub(crate) async fn set_on_call_handler(
+    State(group_state): State<Arc<Group>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Json(body): Json<OnCallBody>,
+) -> Response {
+    let Some(admin) = authenticate(&group_state, &headers).filter(|t| t.admin) else {
+        return unauthorized();
+    };
+
+    let config = OnCallGroupConfig {
+        name,
+        members: body.members,
+        strategy: body.strategy,
+    };
+
+    tracing::info!("{} set on-call group {:?}", admin.name, config);
+
+    group_state.on_call.set(config.clone()).await;
+
+    Json(config).into_response()
+}
+
+pub(crate) async fn remove_on_call_handler(
+    State(group_state): State<Arc<Group>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Response {
+    let Some(admin) = authenticate(&group_state, &headers).filter(|t| t.admin) else {
+        return unauthorized();
+    };
+
+    if !group_state.on_call.remove(&name).await {
+        return (http::StatusCode::NOT_FOUND, "no such on-call group").into_response();
+    }
+
+    tracing::info!("{} removed on-call group {name}", admin.name);
+
+    http::StatusCode::NO_CONTENT.into_response()
+}
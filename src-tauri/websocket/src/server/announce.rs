@@ -0,0 +1,306 @@
+use crate::message::{ChatMessage, MessageContent};
+use crate::server::audit::AuditEvent;
+use crate::server::config::AnnouncementConfig;
+use crate::server::{deliver_prompt, Group, SERVRE_IDENTITY};
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+use serde::Serialize;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+const ANNOUNCEMENT_TICK: Duration = Duration::from_secs(1);
+
+/// An announcement as shown by the admin api.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct AnnouncementInfo {
+    id: u64,
+    #[serde(flatten)]
+    config: AnnouncementConfig,
+    next_run: Option<DateTime<Utc>>,
+}
+
+struct Announcement {
+    id: u64,
+    config: AnnouncementConfig,
+    schedule: Schedule,
+    timezone: Tz,
+    next_run: Option<DateTime<Utc>>,
+}
+
+impl Announcement {
+    fn new(id: u64, config: AnnouncementConfig) -> anyhow::Result<Self> {
+        // the cron crate wants seconds, the usual five fields mean "at second 0"
+        let fields = config.schedule.split_whitespace().collect::<Vec<_>>();
+        let expression = match fields.as_slice() {
+            [minute, hour, day, month, weekdays] => format!(
+                "0 {minute} {hour} {day} {month} {}",
+                cron_weekdays(weekdays)?
+            ),
+            _ => config.schedule.clone(),
+        };
+
+        let schedule = Schedule::from_str(&expression)
+            .map_err(|e| anyhow!("invalid schedule '{}': {e}", config.schedule))?;
+
+        let timezone = match &config.timezone {
+            Some(name) => name
+                .parse::<Tz>()
+                .map_err(|_| anyhow!("unknown timezone '{name}'"))?,
+            None => Tz::UTC,
+        };
+
+        let mut announcement = Self {
+            id,
+            config,
+            schedule,
+            timezone,
+            next_run: None,
+        };
+        announcement.next_run = announcement.upcoming(Utc::now());
+
+        Ok(announcement)
+    }
+
+    fn upcoming(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.schedule
+            .after(&after.with_timezone(&self.timezone))
+            .next()
+            .map(|at| at.with_timezone(&Utc))
+    }
+
+    fn info(&self) -> AnnouncementInfo {
+        AnnouncementInfo {
+            id: self.id,
+            config: self.config.clone(),
+            next_run: self.next_run,
+        }
+    }
+}
+
+// the usual cron numbers weekdays from 0 (sunday) to 6, with 7 as another sunday.
+// the cron crate goes from 1 (sunday) to 7, so numbers are moved up by one.
+fn cron_weekdays(field: &str) -> anyhow::Result<String> {
+    let mut items = Vec::new();
+
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, Some(step)),
+            None => (item, None),
+        };
+
+        let converted = match range.split_once('-') {
+            // ends on sunday, which is at the start of the week now
+            Some((start, "7")) if step.is_none() => match cron_weekday(start)?.as_str() {
+                "1" => "1-7".to_string(),
+                start => format!("{start}-7,1"),
+            },
+            Some((_, "7")) => {
+                return Err(anyhow!(
+                    "day of week range '{item}' can't end on 7 and have a step"
+                ))
+            }
+            Some((start, end)) => format!("{}-{}", cron_weekday(start)?, cron_weekday(end)?),
+            None => cron_weekday(range)?,
+        };
+
+        items.push(match step {
+            Some(step) => format!("{converted}/{step}"),
+            None => converted,
+        });
+    }
+
+    Ok(items.join(","))
+}
+
+// names and "*" stay as they are
+fn cron_weekday(value: &str) -> anyhow::Result<String> {
+    match value.parse::<u8>() {
+        Ok(day @ 0..=6) => Ok((day + 1).to_string()),
+        Ok(7) => Ok("1".to_string()),
+        Ok(day) => Err(anyhow!("day of week {day} is not between 0 and 7")),
+        Err(_) => Ok(value.to_string()),
+    }
+}
+
+#[derive(Default)]
+struct AnnouncementsState {
+    next_id: u64,
+    announcements: Vec<Announcement>,
+}
+
+/// Recurring prompts from the server. They start out as the ones in the config,
+/// changes through the admin api last until the server restarts.
+pub(crate) struct Announcements {
+    state: Mutex<AnnouncementsState>,
+}
+
+impl Announcements {
+    pub fn new(configs: &[AnnouncementConfig]) -> anyhow::Result<Self> {
+        let mut state = AnnouncementsState::default();
+
+        for config in configs {
+            state.next_id += 1;
+            state
+                .announcements
+                .push(Announcement::new(state.next_id, config.clone())?);
+        }
+
+        Ok(Self {
+            state: Mutex::new(state),
+        })
+    }
+
+    pub async fn add(&self, config: AnnouncementConfig) -> anyhow::Result<AnnouncementInfo> {
+        let mut state = self.state.lock().await;
+
+        let announcement = Announcement::new(state.next_id + 1, config)?;
+        let info = announcement.info();
+
+        state.next_id += 1;
+        state.announcements.push(announcement);
+
+        Ok(info)
+    }
+
+    /// returns false if there was no announcement with that id.
+    pub async fn remove(&self, id: u64) -> bool {
+        let mut state = self.state.lock().await;
+        let before = state.announcements.len();
+
+        state.announcements.retain(|a| a.id != id);
+
+        state.announcements.len() != before
+    }
+
+    pub async fn list(&self) -> Vec<AnnouncementInfo> {
+        self.state
+            .lock()
+            .await
+            .announcements
+            .iter()
+            .map(Announcement::info)
+            .collect()
+    }
+
+    // runs that were missed, e.g. while the machine was asleep, are sent once
+    async fn take_due(&self) -> Vec<AnnouncementConfig> {
+        let now = Utc::now();
+        let mut state = self.state.lock().await;
+        let mut due = Vec::new();
+
+        for announcement in &mut state.announcements {
+            if announcement.next_run.is_some_and(|at| at <= now) {
+                due.push(announcement.config.clone());
+                announcement.next_run = announcement.upcoming(now);
+            }
+        }
+
+        due
+    }
+}
+
+// sends announcements when they are due, as prompts from the server.
+pub(crate) fn spawn_announcer(group_state: Arc<Group>) {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(ANNOUNCEMENT_TICK);
+
+        loop {
+            tick.tick().await;
+
+            for announcement in group_state.announcements.take_due().await {
+                // bots have no presence, so "everyone" is only the people
+                let receivers = if announcement.to.is_empty() {
+                    group_state.presences.read().await.keys().cloned().collect()
+                } else {
+                    announcement.to
+                };
+
+                for receiver in receivers {
+                    group_state.audit.record(AuditEvent::Message {
+                        from: SERVRE_IDENTITY.to_string(),
+                        to: receiver.clone(),
+                        kind: "prompt",
+                        body: Some(announcement.text.clone()),
+                    });
+
+                    let msg = ChatMessage::new(
+                        SERVRE_IDENTITY,
+                        &receiver,
+                        MessageContent::Prompt(announcement.text.clone()),
+                    );
+
+                    let status = deliver_prompt(&group_state, msg).await;
+
+                    tracing::debug!("announcement to {receiver}: {status:?}");
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Datelike, Weekday};
+
+    fn announcement(schedule: &str) -> anyhow::Result<Announcement> {
+        Announcement::new(
+            1,
+            AnnouncementConfig {
+                schedule: schedule.to_string(),
+                timezone: None,
+                text: "stand-up time!".to_string(),
+                to: Vec::new(),
+            },
+        )
+    }
+
+    fn run_days(announcement: &Announcement) -> Vec<Weekday> {
+        announcement
+            .schedule
+            .upcoming(Utc)
+            .take(7)
+            .map(|at| at.weekday())
+            .collect()
+    }
+
+    #[test]
+    fn converts_cron_weekday_numbers() {
+        assert_eq!(cron_weekdays("1-5").unwrap(), "2-6");
+        assert_eq!(cron_weekdays("0,6").unwrap(), "1,7");
+        assert_eq!(cron_weekdays("7").unwrap(), "1");
+        assert_eq!(cron_weekdays("5-7").unwrap(), "6-7,1");
+        assert_eq!(cron_weekdays("0-7").unwrap(), "1-7");
+        assert_eq!(cron_weekdays("1-5/2").unwrap(), "2-6/2");
+        assert_eq!(cron_weekdays("Mon-Fri").unwrap(), "Mon-Fri");
+        assert_eq!(cron_weekdays("*").unwrap(), "*");
+        assert!(cron_weekdays("8").is_err());
+    }
+
+    #[test]
+    fn runs_five_field_schedules_on_cron_weekdays() {
+        let weekdays = run_days(&announcement("0 10 * * 1-5").unwrap());
+        assert!(weekdays
+            .iter()
+            .all(|day| !matches!(day, Weekday::Sat | Weekday::Sun)));
+        assert_eq!(weekdays.len(), 7);
+
+        let sundays = run_days(&announcement("0 10 * * 0").unwrap());
+        assert!(sundays.iter().all(|day| *day == Weekday::Sun));
+
+        let weekend = run_days(&announcement("0 10 * * 6-7").unwrap());
+        assert!(weekend
+            .iter()
+            .all(|day| matches!(day, Weekday::Sat | Weekday::Sun)));
+    }
+
+    #[test]
+    fn passes_schedules_with_seconds_through() {
+        let sundays = run_days(&announcement("0 0 10 * * 1").unwrap());
+        assert!(sundays.iter().all(|day| *day == Weekday::Sun));
+    }
+}
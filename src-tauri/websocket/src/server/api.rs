@@ -0,0 +1,124 @@
+use crate::message::{ChatMessage, DeliveryStatus, MessageContent, Priority};
+use crate::server::config::ApiToken;
+use crate::server::{audit_prompt, deliver_on_call, deliver_prompt, Group};
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use axum::{http, Json};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+
+// Plain http endpoints for scripts and CI jobs that don't want to speak websocket.
+// Callers authenticate with `Authorization: Bearer <token>` using one of the
+// tokens from the `api` section of the server config. Requests either name
+// their receivers in `to` or pick one member of an on-call group with `on_call`.
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct SendRequest {
+    #[serde(default)]
+    to: Vec<String>,
+    /// sends to one available member of this on-call group instead of `to`.
+    #[serde(default)]
+    on_call: Option<String>,
+    text: String,
+    #[serde(default)]
+    character: Option<String>,
+    #[serde(default)]
+    priority: Priority,
+    /// seconds after which a prompt that is still waiting for its receiver is dropped.
+    #[serde(default)]
+    ttl_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct SendResponse {
+    results: BTreeMap<String, DeliveryStatus>,
+}
+
+// returns the api token the request carries as bearer token. compared in
+// constant time so response times don't give away how much of a token was right.
+pub(crate) fn authenticate<'a>(
+    group_state: &'a Group,
+    headers: &HeaderMap,
+) -> Option<&'a ApiToken> {
+    let token = headers
+        .get(http::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")?;
+
+    group_state
+        .api_tokens
+        .iter()
+        .find(|t| bool::from(t.token.as_bytes().ct_eq(token.as_bytes())))
+}
+
+pub(crate) fn unauthorized() -> Response {
+    Response::builder()
+        .status(http::StatusCode::UNAUTHORIZED)
+        .body("invalid api token".into())
+        .unwrap()
+}
+
+pub(crate) async fn send_handler(
+    State(group_state): State<Arc<Group>>,
+    headers: HeaderMap,
+    Json(request): Json<SendRequest>,
+) -> Response {
+    let Some(sender) = authenticate(&group_state, &headers).map(|t| t.name.clone()) else {
+        return unauthorized();
+    };
+
+    let mut results = BTreeMap::new();
+
+    if let Some(group) = request.on_call {
+        let msg = ChatMessage::new(&sender, "", MessageContent::Prompt(request.text))
+            .with_priority(request.priority)
+            .with_character(request.character)
+            .with_on_call(Some(group))
+            .with_ttl(request.ttl_secs);
+
+        return match deliver_on_call(&group_state, msg).await {
+            Ok(member) => {
+                results.insert(member, DeliveryStatus::Delivered);
+
+                Json(SendResponse { results }).into_response()
+            }
+            Err(e) => (http::StatusCode::CONFLICT, Json(e)).into_response(),
+        };
+    }
+
+    let mut receivers = request.to;
+    receivers.sort();
+    receivers.dedup();
+
+    if receivers.len() > group_state.max_recipients {
+        let reason = format!(
+            "you can't send to more than {} users at once",
+            group_state.max_recipients
+        );
+
+        return (http::StatusCode::BAD_REQUEST, reason).into_response();
+    }
+
+    for receiver in receivers {
+        let msg = ChatMessage::new(
+            &sender,
+            &receiver,
+            MessageContent::Prompt(request.text.clone()),
+        )
+        .with_priority(request.priority)
+        .with_character(request.character.clone())
+        .with_ttl(request.ttl_secs);
+
+        audit_prompt(&group_state, &msg);
+
+        let status = deliver_prompt(&group_state, msg).await;
+
+        results.insert(receiver, status);
+    }
+
+    Json(SendResponse { results }).into_response()
+}
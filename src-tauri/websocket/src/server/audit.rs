@@ -0,0 +1,300 @@
+use crate::server::config::AuditConfig;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum AuditEvent {
+    Connected {
+        user: String,
+        addr: SocketAddr,
+    },
+    ConnectionRejected {
+        user: String,
+        addr: SocketAddr,
+        reason: String,
+    },
+    Disconnected {
+        user: String,
+    },
+    Message {
+        from: String,
+        to: String,
+        kind: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        body: Option<String>,
+    },
+    MessageRejected {
+        from: String,
+        to: String,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct AuditRecord {
+    timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    event: AuditEvent,
+}
+
+/// Writes audit events as json lines. Writing happens on a dedicated thread so
+/// recording an event never blocks the connection that produced it.
+pub(crate) struct AuditLog {
+    tx: Option<mpsc::Sender<AuditRecord>>,
+    redact_bodies: bool,
+}
+
+impl AuditLog {
+    pub fn disabled() -> Self {
+        Self {
+            tx: None,
+            redact_bodies: true,
+        }
+    }
+
+    pub fn from_config(config: &AuditConfig) -> anyhow::Result<Self> {
+        let Some(path) = &config.path else {
+            return Ok(Self::disabled());
+        };
+
+        let mut writer = RotatingWriter::open(path.clone(), config.max_bytes, config.max_files)?;
+        let (tx, rx) = mpsc::channel::<AuditRecord>();
+
+        std::thread::spawn(move || {
+            for record in rx {
+                if let Err(e) = writer.write_record(&record) {
+                    tracing::error!("failed to write audit record: {:?}", e);
+                }
+            }
+        });
+
+        Ok(Self {
+            tx: Some(tx),
+            redact_bodies: config.redact_bodies,
+        })
+    }
+
+    pub fn record(&self, event: AuditEvent) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+
+        let event = match event {
+            AuditEvent::Message { from, to, kind, .. } if self.redact_bodies => {
+                AuditEvent::Message {
+                    from,
+                    to,
+                    kind,
+                    body: None,
+                }
+            }
+            event => event,
+        };
+
+        let record = AuditRecord {
+            timestamp: Utc::now(),
+            event,
+        };
+
+        if tx.send(record).is_err() {
+            tracing::error!("audit writer is gone, dropping audit record");
+        }
+    }
+}
+
+// appends to `path` and moves it to `path.1`, `path.2`, ... once it grows past max_bytes
+struct RotatingWriter {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl RotatingWriter {
+    fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> anyhow::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_bytes,
+            max_files,
+        })
+    }
+
+    fn write_record(&mut self, record: &AuditRecord) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        if self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+
+        self.file.write_all(&line)?;
+        self.size += line.len() as u64;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> anyhow::Result<()> {
+        self.file.flush()?;
+
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for i in (1..self.max_files).rev() {
+                let from = rotated_path(&self.path, i);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(&self.path, i + 1))?;
+                }
+            }
+
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{index}"));
+
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_log_path() -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("ferris-audit-{}", uuid::Uuid::new_v4()))
+            .join("audit.log")
+    }
+
+    fn disconnected(user: &str) -> AuditRecord {
+        AuditRecord {
+            timestamp: Utc::now(),
+            event: AuditEvent::Disconnected {
+                user: user.to_string(),
+            },
+        }
+    }
+
+    // users in the file, oldest first, or None if there is no such file
+    fn users_in(path: &Path) -> Option<Vec<String>> {
+        let content = fs::read_to_string(path).ok()?;
+
+        let users = content
+            .lines()
+            .map(|line| {
+                let value: serde_json::Value = serde_json::from_str(line).unwrap();
+                value["user"].as_str().unwrap().to_string()
+            })
+            .collect();
+
+        Some(users)
+    }
+
+    #[test]
+    fn rotates_into_numbered_files_and_prunes_the_oldest() {
+        let path = temp_log_path();
+        // room for exactly one record per file
+        let mut writer = RotatingWriter::open(path.clone(), 1, 2).unwrap();
+
+        for user in ["u1", "u2", "u3", "u4"] {
+            writer.write_record(&disconnected(user)).unwrap();
+        }
+
+        assert_eq!(users_in(&path), Some(vec!["u4".to_string()]));
+        assert_eq!(
+            users_in(&rotated_path(&path, 1)),
+            Some(vec!["u3".to_string()])
+        );
+        assert_eq!(
+            users_in(&rotated_path(&path, 2)),
+            Some(vec!["u2".to_string()])
+        );
+        assert_eq!(users_in(&rotated_path(&path, 3)), None);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn keeps_appending_below_the_size_limit() {
+        let path = temp_log_path();
+        let mut writer = RotatingWriter::open(path.clone(), 1024, 2).unwrap();
+
+        writer.write_record(&disconnected("u1")).unwrap();
+        writer.write_record(&disconnected("u2")).unwrap();
+
+        // picks up where the file left off after a restart
+        let mut writer = RotatingWriter::open(path.clone(), 1024, 2).unwrap();
+        writer.write_record(&disconnected("u3")).unwrap();
+
+        assert_eq!(
+            users_in(&path),
+            Some(vec!["u1".to_string(), "u2".to_string(), "u3".to_string()])
+        );
+        assert_eq!(users_in(&rotated_path(&path, 1)), None);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn drops_old_records_without_rotated_files() {
+        let path = temp_log_path();
+        let mut writer = RotatingWriter::open(path.clone(), 1, 0).unwrap();
+
+        for user in ["u1", "u2", "u3"] {
+            writer.write_record(&disconnected(user)).unwrap();
+        }
+
+        assert_eq!(users_in(&path), Some(vec!["u3".to_string()]));
+        assert_eq!(users_in(&rotated_path(&path, 1)), None);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn leaves_bodies_out_when_redacting() {
+        let (tx, rx) = mpsc::channel();
+        let log = AuditLog {
+            tx: Some(tx),
+            redact_bodies: true,
+        };
+
+        log.record(AuditEvent::Message {
+            from: "alice".to_string(),
+            to: "bob".to_string(),
+            kind: "prompt",
+            body: Some("the secret plan".to_string()),
+        });
+
+        let line = serde_json::to_string(&rx.recv().unwrap()).unwrap();
+
+        assert!(line.contains("\"from\":\"alice\""));
+        assert!(!line.contains("body"));
+        assert!(!line.contains("the secret plan"));
+    }
+}
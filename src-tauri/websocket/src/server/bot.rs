@@ -0,0 +1,274 @@
+use crate::message::{ChatMessage, MessageContent};
+use crate::server::config::{BotConfig, BotKind};
+use crate::server::{route_message, scheduler, Group};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::Sender;
+
+const FORTUNES: &[&str] = &[
+    "A journey of a thousand miles begins with cargo new.",
+    "The borrow checker is your friend, even when it doesn't feel like it.",
+    "Fearless concurrency is just concurrency you have already debugged.",
+    "Today is a good day to read the error message until the end.",
+    "You will soon clone something you should have borrowed.",
+    "Go home, it's late. 🦀",
+];
+
+/// A virtual user living inside the server. Bots show up in the online users
+/// list and receive every message addressed to them, without a websocket.
+#[async_trait]
+pub trait Bot: Send + Sync {
+    /// the username the bot is registered under.
+    fn name(&self) -> &str;
+
+    /// called for every message sent to the bot, including server errors about
+    /// the bot's own messages. each call runs on its own task.
+    async fn on_message(&self, message: ChatMessage, ctx: BotContext);
+}
+
+/// Handle given to bots to talk back to users.
+#[derive(Clone)]
+pub struct BotContext {
+    name: String,
+    group_state: Arc<Group>,
+    tx: Sender<ChatMessage>,
+}
+
+impl BotContext {
+    /// sends a prompt from the bot, going through the same filters and checks
+    /// as messages sent by regular users.
+    pub async fn send_text(&self, receiver: &str, text: String) -> anyhow::Result<()> {
+        let msg = ChatMessage::new(&self.name, receiver, MessageContent::Prompt(text));
+
+        route_message(&self.group_state, &self.name, &self.tx, msg).await
+    }
+
+    /// sends a prompt from the bot once `delay` has passed. it is kept by the
+    /// server's scheduler, so it survives restarts and counts against the bot's
+    /// limit of scheduled prompts.
+    pub async fn schedule_text(
+        &self,
+        receiver: &str,
+        text: String,
+        delay: Duration,
+    ) -> anyhow::Result<()> {
+        let deliver_at = scheduler::deliver_at_after(delay)
+            .ok_or_else(|| anyhow!("that is too far in the future"))?;
+
+        self.group_state
+            .scheduler
+            .schedule(&self.name, receiver, text, deliver_at)
+            .await
+            .map(|_| ())
+            .map_err(|reason| anyhow!(reason))
+    }
+}
+
+// registers the bot as an online user and feeds it every message sent to it.
+pub(crate) async fn register_bot(group_state: Arc<Group>, bot: Arc<dyn Bot>) {
+    let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+    let name = bot.name().to_string();
+
+    group_state
+        .user_sinks
+        .write()
+        .await
+        .insert(name.clone(), tx.clone());
+    group_state.bots.write().await.insert(name.clone());
+
+    tracing::info!("registered bot {name}");
+
+    let ctx = BotContext {
+        name,
+        group_state,
+        tx,
+    };
+
+    tokio::spawn(async move {
+        while let Some(msg) = rx.recv().await {
+            let bot = Arc::clone(&bot);
+            let ctx = ctx.clone();
+
+            tokio::spawn(async move { bot.on_message(msg, ctx).await });
+        }
+    });
+}
+
+pub(crate) fn bot_from_config(config: &BotConfig) -> Arc<dyn Bot> {
+    let name = config
+        .name
+        .clone()
+        .unwrap_or_else(|| config.kind.default_name().to_string());
+
+    match config.kind {
+        BotKind::Echo => Arc::new(EchoBot::new(name)),
+        BotKind::Fortune => Arc::new(FortuneBot::new(name)),
+        BotKind::Remind => Arc::new(RemindBot::new(name)),
+    }
+}
+
+/// Sends every prompt back to whoever sent it.
+pub struct EchoBot {
+    name: String,
+}
+
+impl EchoBot {
+    pub fn new(name: String) -> Self {
+        Self { name }
+    }
+}
+
+#[async_trait]
+impl Bot for EchoBot {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn on_message(&self, message: ChatMessage, ctx: BotContext) {
+        if let MessageContent::Prompt(text) = message.content {
+            if let Err(e) = ctx.send_text(&message.from, text).await {
+                tracing::error!("echo bot failed to reply: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Answers any prompt with a random fortune.
+pub struct FortuneBot {
+    name: String,
+}
+
+impl FortuneBot {
+    pub fn new(name: String) -> Self {
+        Self { name }
+    }
+}
+
+#[async_trait]
+impl Bot for FortuneBot {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn on_message(&self, message: ChatMessage, ctx: BotContext) {
+        if let MessageContent::Prompt(_) = message.content {
+            // good enough randomness for fortunes
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .subsec_nanos() as usize;
+            let fortune = FORTUNES[nanos % FORTUNES.len()];
+
+            if let Err(e) = ctx.send_text(&message.from, fortune.to_string()).await {
+                tracing::error!("fortune bot failed to reply: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Schedules a popup for later, e.g. "10m stand up" or "1h30m go home".
+pub struct RemindBot {
+    name: String,
+}
+
+impl RemindBot {
+    pub fn new(name: String) -> Self {
+        Self { name }
+    }
+}
+
+#[async_trait]
+impl Bot for RemindBot {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn on_message(&self, message: ChatMessage, ctx: BotContext) {
+        let MessageContent::Prompt(text) = message.content else {
+            return;
+        };
+
+        let reply = match text.trim().split_once(' ') {
+            Some((delay, reminder)) => parse_duration(delay).map(|d| (d, reminder.to_string())),
+            None => None,
+        };
+
+        let Some((delay, reminder)) = reply else {
+            let usage = "usage: <delay> <text>, e.g. \"10m stand up\" or \"1h30m go home\"";
+
+            if let Err(e) = ctx.send_text(&message.from, usage.to_string()).await {
+                tracing::error!("remind bot failed to reply: {:?}", e);
+            }
+
+            return;
+        };
+
+        if let Err(e) = ctx.schedule_text(&message.from, reminder, delay).await {
+            let reply = format!("can't remind you: {e}");
+
+            if let Err(e) = ctx.send_text(&message.from, reply).await {
+                tracing::error!("remind bot failed to reply: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Parses durations like "90s", "10m" or "1h30m".
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let mut total = 0u64;
+    let mut number = String::new();
+
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let value: u64 = number.parse().ok()?;
+        number.clear();
+
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 60 * 60 * 24,
+            _ => return None,
+        };
+
+        total = total.checked_add(value.checked_mul(unit)?)?;
+    }
+
+    if !number.is_empty() || total == 0 {
+        return None;
+    }
+
+    Some(Duration::from_secs(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1h30m"), Some(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("2d"), Some(Duration::from_secs(172_800)));
+    }
+
+    #[test]
+    fn rejects_malformed_durations() {
+        assert_eq!(parse_duration("10"), None);
+        assert_eq!(parse_duration("10x"), None);
+        assert_eq!(parse_duration("0m"), None);
+        assert_eq!(parse_duration("m"), None);
+    }
+
+    #[test]
+    fn rejects_durations_that_overflow() {
+        assert_eq!(parse_duration("99999999999999999d"), None);
+        assert_eq!(parse_duration("18446744073709551615s1s"), None);
+    }
+}
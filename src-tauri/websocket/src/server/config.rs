@@ -0,0 +1,343 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Server side configuration. Every field has a default so an empty config file
+/// (or no config file at all) gives the same behaviour as before.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// directory for state that has to survive restarts, nothing is persisted when unset.
+    pub data_dir: Option<PathBuf>,
+    pub filters: FiltersConfig,
+    pub bots: Vec<BotConfig>,
+    pub scheduler: SchedulerConfig,
+    pub audit: AuditConfig,
+    pub api: ApiConfig,
+    pub webhooks: WebhooksConfig,
+    pub usernames: UsernameConfig,
+    /// at most one typing indicator is relayed per sender and receiver in this window.
+    pub typing_throttle_ms: u64,
+    pub replay: ReplayConfig,
+    pub quotas: QuotaConfig,
+    pub connections: ConnectionsConfig,
+    /// sent to users when they connect, "{username}" and "{online}" are filled in.
+    pub motd: Option<String>,
+    /// recurring prompts from the server, see `AnnouncementConfig`.
+    pub announcements: Vec<AnnouncementConfig>,
+    /// users a single prompt can be sent to at once.
+    pub max_recipients: usize,
+    /// groups a prompt can be sent to, it goes to one available member.
+    pub on_call: Vec<OnCallGroupConfig>,
+    /// a message key is only acted on once in this window, see `ChatMessage::key`.
+    pub dedupe_window_secs: u64,
+    /// senders can take a prompt back for this long after sending it.
+    pub recall_window_secs: u64,
+    /// senders, api tokens included, whose urgent prompts get through do-not-disturb
+    /// mode, "*" for everyone. nobody but the server by default. urgent prompts from
+    /// anyone else count as normal ones.
+    pub urgent_senders: Vec<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            data_dir: None,
+            filters: FiltersConfig::default(),
+            bots: Vec::new(),
+            scheduler: SchedulerConfig::default(),
+            audit: AuditConfig::default(),
+            api: ApiConfig::default(),
+            webhooks: WebhooksConfig::default(),
+            usernames: UsernameConfig::default(),
+            typing_throttle_ms: 2000,
+            replay: ReplayConfig::default(),
+            quotas: QuotaConfig::default(),
+            connections: ConnectionsConfig::default(),
+            motd: None,
+            announcements: Vec::new(),
+            max_recipients: 50,
+            on_call: Vec::new(),
+            dedupe_window_secs: 600,
+            recall_window_secs: 120,
+            urgent_senders: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FiltersConfig {
+    /// prompts longer than this many characters are rejected.
+    pub max_length: Option<usize>,
+    /// replaces anything that looks like a link with a placeholder.
+    pub strip_links: bool,
+    pub word_list: Option<WordListConfig>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct WordListConfig {
+    pub words: Vec<String>,
+    /// names of the word lists shipped with the server, e.g. "en" or "fa".
+    pub builtin: Vec<String>,
+    pub action: WordListAction,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WordListAction {
+    /// replaces matched words with asterisks.
+    #[default]
+    Mask,
+    Reject,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    pub max_scheduled_per_user: usize,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            max_scheduled_per_user: 50,
+        }
+    }
+}
+
+/// Daily limits on prompts, counted per UTC day. Nothing is counted when both are unset.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct QuotaConfig {
+    /// prompts a user may send per day, to anyone. bots are not limited.
+    pub max_sent_per_day: Option<u32>,
+    /// prompts a user accepts from any one sender per day.
+    pub max_received_per_sender_per_day: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ConnectionsConfig {
+    /// open websocket and event stream connections the server accepts in total.
+    pub max_total: Option<usize>,
+    /// open connections accepted from a single ip address.
+    pub max_per_ip: Option<usize>,
+    /// origins browsers may connect from, e.g. "https://example.com", or "*" for
+    /// any. requests without an Origin header are always let through.
+    pub allowed_origins: Vec<String>,
+    /// how often websocket clients are pinged.
+    pub ping_interval_secs: u64,
+    /// websocket clients we heard nothing from for this long, not even a pong, are
+    /// disconnected so their name is free again.
+    pub idle_timeout_secs: u64,
+}
+
+impl Default for ConnectionsConfig {
+    fn default() -> Self {
+        Self {
+            max_total: None,
+            max_per_ip: None,
+            allowed_origins: Vec::new(),
+            ping_interval_secs: 30,
+            idle_timeout_secs: 90,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// json lines file to write audit records to, auditing is off when unset.
+    pub path: Option<PathBuf>,
+    /// the file is rotated once it would grow past this size.
+    pub max_bytes: u64,
+    /// number of rotated files to keep next to the current one.
+    pub max_files: usize,
+    /// leave message text out of the records.
+    pub redact_bodies: bool,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_bytes: 10 * 1024 * 1024,
+            max_files: 5,
+            redact_bodies: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ApiConfig {
+    /// the http api is only usable with one of these tokens.
+    pub tokens: Vec<ApiToken>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiToken {
+    /// shows up as the sender of messages sent with this token.
+    pub name: String,
+    pub token: String,
+    /// admin tokens can also change server settings at runtime, e.g. the motd.
+    #[serde(default)]
+    pub admin: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WebhooksConfig {
+    pub endpoints: Vec<WebhookEndpoint>,
+    /// how many times a failed delivery is retried before giving up.
+    pub max_retries: u32,
+    /// wait before the first retry, doubled after every failed attempt.
+    pub initial_backoff_ms: u64,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            endpoints: Vec::new(),
+            max_retries: 5,
+            initial_backoff_ms: 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookEndpoint {
+    pub url: String,
+    /// key for signing request bodies, requests are unsigned when unset.
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// event names to send to this endpoint, e.g. "user_connected". empty means all.
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UsernameConfig {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// letters of any script, as long as a single name doesn't mix scripts.
+    pub allow_letters: bool,
+    pub allow_digits: bool,
+    /// other characters allowed in usernames.
+    pub allowed_symbols: String,
+    /// reject names like "pаypal" that mix latin and cyrillic letters.
+    pub reject_mixed_scripts: bool,
+    /// names nobody can connect with, on top of the server's own identity.
+    pub reserved: Vec<String>,
+}
+
+impl Default for UsernameConfig {
+    fn default() -> Self {
+        Self {
+            min_length: 2,
+            max_length: 32,
+            allow_letters: true,
+            allow_digits: true,
+            allowed_symbols: "_-.".to_string(),
+            reject_mixed_scripts: true,
+            reserved: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReplayConfig {
+    /// number of recent prompts kept per user for replaying after a reconnect.
+    pub buffer_size: usize,
+    /// prompts for a disconnected user are kept for them this long.
+    pub resume_window_secs: u64,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            buffer_size: 100,
+            resume_window_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnnouncementConfig {
+    /// cron expression, e.g. "0 10 * * Mon-Fri" or "0 10 * * 1-5" for weekdays at
+    /// 10:00. days of the week are numbered from 0 (sunday) to 7 (sunday again).
+    /// with a leading seconds field it is read the way the cron crate does, which
+    /// numbers them from 1 (sunday) to 7 (saturday).
+    pub schedule: String,
+    /// IANA name of the timezone the schedule is in, e.g. "Asia/Tehran". UTC when unset.
+    #[serde(default)]
+    pub timezone: Option<String>,
+    pub text: String,
+    /// users to send it to, everyone online when empty.
+    #[serde(default)]
+    pub to: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OnCallGroupConfig {
+    pub name: String,
+    /// in priority order, for the `priority` strategy.
+    pub members: Vec<String>,
+    #[serde(default)]
+    pub strategy: RoutingStrategy,
+}
+
+/// How the member of an on-call group who gets a prompt is picked. Members who
+/// are offline, in do-not-disturb mode or have blocked the sender are skipped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutingStrategy {
+    /// takes turns, starting after whoever got the previous prompt.
+    #[default]
+    RoundRobin,
+    /// the first member in the list.
+    Priority,
+    /// whoever got a prompt from the group the longest time ago.
+    LeastRecentlyPinged,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BotConfig {
+    pub kind: BotKind,
+    /// username of the bot, defaults to the kind of the bot.
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BotKind {
+    Echo,
+    Fortune,
+    Remind,
+}
+
+impl BotKind {
+    pub fn default_name(&self) -> &'static str {
+        match self {
+            BotKind::Echo => "echo",
+            BotKind::Fortune => "fortune",
+            BotKind::Remind => "remind",
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn from_file(file_name: &str) -> anyhow::Result<Self> {
+        let config = config::Config::builder()
+            .add_source(config::File::with_name(file_name))
+            .add_source(config::Environment::with_prefix("X_FERRIS_SAY_SERVER").separator("__"))
+            .build()?;
+
+        let config = config.try_deserialize::<ServerConfig>()?;
+
+        Ok(config)
+    }
+}
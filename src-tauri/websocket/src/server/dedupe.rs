@@ -0,0 +1,49 @@
+use std::collections::{HashSet, VecDeque};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+#[derive(Default)]
+struct Seen {
+    keys: HashSet<(String, String)>,
+    // oldest first, for forgetting keys once they leave the window
+    order: VecDeque<(Instant, (String, String))>,
+}
+
+/// Remembers the idempotency keys of recent messages per sender.
+pub(crate) struct Dedupe {
+    seen: Mutex<Seen>,
+    window: Duration,
+}
+
+impl Dedupe {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            seen: Mutex::new(Seen::default()),
+            window,
+        }
+    }
+
+    /// returns false if the sender already used the key within the window.
+    pub async fn first_seen(&self, sender: &str, key: &str) -> bool {
+        let mut seen = self.seen.lock().await;
+
+        while let Some((at, _)) = seen.order.front() {
+            if at.elapsed() < self.window {
+                break;
+            }
+
+            let (_, entry) = seen.order.pop_front().unwrap();
+            seen.keys.remove(&entry);
+        }
+
+        let entry = (sender.to_string(), key.to_string());
+
+        if !seen.keys.insert(entry.clone()) {
+            return false;
+        }
+
+        seen.order.push_back((Instant::now(), entry));
+
+        true
+    }
+}
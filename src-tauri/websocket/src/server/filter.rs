@@ -0,0 +1,322 @@
+use crate::server::config::{FiltersConfig, WordListAction, WordListConfig};
+use std::collections::HashSet;
+
+const LINK_PLACEHOLDER: &str = "[link removed]";
+
+/// What should happen to a prompt after passing through a filter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterAction {
+    Allow,
+    Reject(String),
+    Rewrite(String),
+}
+
+/// Every prompt passes through the configured filters before it is delivered.
+/// Filters run in order and each one sees the text as rewritten by the previous ones.
+pub trait MessageFilter: Send + Sync {
+    fn filter(&self, from: &str, to: &str, text: &str) -> FilterAction;
+}
+
+pub struct MaxLengthFilter {
+    max_length: usize,
+}
+
+impl MaxLengthFilter {
+    pub fn new(max_length: usize) -> Self {
+        Self { max_length }
+    }
+}
+
+impl MessageFilter for MaxLengthFilter {
+    fn filter(&self, _from: &str, _to: &str, text: &str) -> FilterAction {
+        if text.chars().count() > self.max_length {
+            FilterAction::Reject(format!(
+                "message is longer than {} characters",
+                self.max_length
+            ))
+        } else {
+            FilterAction::Allow
+        }
+    }
+}
+
+pub struct LinkFilter;
+
+impl MessageFilter for LinkFilter {
+    fn filter(&self, _from: &str, _to: &str, text: &str) -> FilterAction {
+        let mut rewritten = String::with_capacity(text.len());
+        let mut found = false;
+
+        for (is_token, chunk) in split_runs(text, |c| !c.is_whitespace()) {
+            match link_start(chunk).filter(|_| is_token) {
+                Some(start) => {
+                    found = true;
+                    rewritten.push_str(&chunk[..start]);
+                    rewritten.push_str(LINK_PLACEHOLDER);
+                }
+                None => rewritten.push_str(chunk),
+            }
+        }
+
+        if found {
+            FilterAction::Rewrite(rewritten)
+        } else {
+            FilterAction::Allow
+        }
+    }
+}
+
+// byte offset of the first link in the token, e.g. 4 in "see:https://x". a
+// link starts the token or follows punctuation, so "awww.x" is not one.
+fn link_start(token: &str) -> Option<usize> {
+    // ascii lower casing keeps the byte offsets of the original
+    let lowered = token.to_ascii_lowercase();
+
+    ["http://", "https://", "ftp://", "www."]
+        .iter()
+        .flat_map(|prefix| lowered.match_indices(prefix).map(|(i, _)| i))
+        .filter(|&i| {
+            lowered[..i]
+                .chars()
+                .next_back()
+                .is_none_or(|c| !c.is_alphanumeric())
+        })
+        .min()
+}
+
+pub struct WordListFilter {
+    words: HashSet<String>,
+    action: WordListAction,
+}
+
+impl WordListFilter {
+    pub fn new(words: impl IntoIterator<Item = String>, action: WordListAction) -> Self {
+        let words = words
+            .into_iter()
+            .map(|w| normalize_word(w.trim()))
+            .filter(|w| !w.is_empty())
+            .collect();
+
+        Self { words, action }
+    }
+
+    pub fn from_config(config: &WordListConfig) -> anyhow::Result<Self> {
+        let mut words = config.words.clone();
+
+        for name in &config.builtin {
+            let list = builtin_word_list(name)
+                .ok_or_else(|| anyhow::anyhow!("unknown builtin word list '{name}'"))?;
+
+            words.extend(list.lines().map(str::to_string));
+        }
+
+        Ok(Self::new(words, config.action))
+    }
+}
+
+impl MessageFilter for WordListFilter {
+    fn filter(&self, _from: &str, _to: &str, text: &str) -> FilterAction {
+        let mut rewritten = String::with_capacity(text.len());
+        let mut found = false;
+
+        for (is_word, chunk) in split_runs(text, is_word_char) {
+            if is_word && self.words.contains(&normalize_word(chunk)) {
+                if self.action == WordListAction::Reject {
+                    return FilterAction::Reject("message contains a forbidden word".to_string());
+                }
+
+                found = true;
+                rewritten.extend(chunk.chars().map(|_| '*'));
+            } else {
+                rewritten.push_str(chunk);
+            }
+        }
+
+        if found {
+            FilterAction::Rewrite(rewritten)
+        } else {
+            FilterAction::Allow
+        }
+    }
+}
+
+fn builtin_word_list(name: &str) -> Option<&'static str> {
+    match name {
+        "en" => Some(include_str!("filter/words_en.txt")),
+        "fa" => Some(include_str!("filter/words_fa.txt")),
+        _ => None,
+    }
+}
+
+// zero width non-joiner is part of persian words (e.g. "بی‌شرف")
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '\u{200c}'
+}
+
+// splits text into alternating runs of chars that do and don't match, keeping
+// everything so the text can be put back together after masking.
+fn split_runs(text: &str, matches: impl Fn(char) -> bool) -> Vec<(bool, &str)> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut in_word = None;
+
+    for (i, c) in text.char_indices() {
+        let is_word = matches(c);
+
+        match in_word {
+            Some(prev) if prev != is_word => {
+                chunks.push((prev, &text[start..i]));
+                start = i;
+            }
+            _ => {}
+        }
+
+        in_word = Some(is_word);
+    }
+
+    if let Some(is_word) = in_word {
+        chunks.push((is_word, &text[start..]));
+    }
+
+    chunks
+}
+
+// lower cases and maps arabic code points that are commonly typed in place of
+// their persian counterparts, so "كثافت" and "کثافت" match the same entry.
+fn normalize_word(word: &str) -> String {
+    word.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            'ي' | 'ى' => 'ی',
+            'ك' => 'ک',
+            'ة' => 'ه',
+            _ => c,
+        })
+        .collect()
+}
+
+/// Builds the filter chain described by the server config.
+pub fn filters_from_config(config: &FiltersConfig) -> anyhow::Result<Vec<Box<dyn MessageFilter>>> {
+    let mut filters: Vec<Box<dyn MessageFilter>> = Vec::new();
+
+    if let Some(max_length) = config.max_length {
+        filters.push(Box::new(MaxLengthFilter::new(max_length)));
+    }
+
+    if config.strip_links {
+        filters.push(Box::new(LinkFilter));
+    }
+
+    if let Some(word_list) = &config.word_list {
+        filters.push(Box::new(WordListFilter::from_config(word_list)?));
+    }
+
+    Ok(filters)
+}
+
+/// Runs text through the filter chain, returning the final text or the reason
+/// it was rejected.
+pub fn apply_filters(
+    filters: &[Box<dyn MessageFilter>],
+    from: &str,
+    to: &str,
+    text: String,
+) -> Result<String, String> {
+    let mut text = text;
+
+    for filter in filters {
+        match filter.filter(from, to, &text) {
+            FilterAction::Allow => {}
+            FilterAction::Reject(reason) => return Err(reason),
+            FilterAction::Rewrite(rewritten) => text = rewritten,
+        }
+    }
+
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewrite(filter: &dyn MessageFilter, text: &str) -> FilterAction {
+        filter.filter("alice", "bob", text)
+    }
+
+    #[test]
+    fn strips_links_after_any_whitespace() {
+        assert_eq!(
+            rewrite(&LinkFilter, "hi\nhttps://x.com\tand WWW.y.org now"),
+            FilterAction::Rewrite("hi\n[link removed]\tand [link removed] now".to_string())
+        );
+    }
+
+    #[test]
+    fn strips_links_after_punctuation() {
+        assert_eq!(
+            rewrite(&LinkFilter, "see:https://x.com (http://y.com)"),
+            FilterAction::Rewrite("see:[link removed] ([link removed]".to_string())
+        );
+    }
+
+    #[test]
+    fn leaves_text_without_links_alone() {
+        assert_eq!(rewrite(&LinkFilter, "awww.so cute"), FilterAction::Allow);
+    }
+
+    #[test]
+    fn rejects_long_prompts() {
+        let filter = MaxLengthFilter::new(3);
+
+        assert_eq!(
+            rewrite(&filter, "سلام"),
+            FilterAction::Reject("message is longer than 3 characters".to_string())
+        );
+        assert_eq!(rewrite(&filter, "hey"), FilterAction::Allow);
+    }
+
+    #[test]
+    fn masks_listed_words_keeping_the_rest() {
+        let filter = WordListFilter::new(["darn".to_string()], WordListAction::Mask);
+
+        assert_eq!(
+            rewrite(&filter, "oh DARN, darned\tdarn!"),
+            FilterAction::Rewrite("oh ****, darned\t****!".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_listed_words() {
+        let filter = WordListFilter::new(["darn".to_string()], WordListAction::Reject);
+
+        assert!(matches!(
+            rewrite(&filter, "darn it"),
+            FilterAction::Reject(_)
+        ));
+    }
+
+    #[test]
+    fn matches_arabic_spellings_of_persian_words() {
+        assert_eq!(normalize_word("كثافت"), "کثافت");
+        assert_eq!(normalize_word("يكي"), "یکی");
+        assert_eq!(normalize_word("ى"), "ی");
+        assert_eq!(normalize_word("خانة"), "خانه");
+
+        let filter = WordListFilter::new(["کثافت".to_string()], WordListAction::Mask);
+
+        assert_eq!(
+            rewrite(&filter, "ای كثافت"),
+            FilterAction::Rewrite("ای *****".to_string())
+        );
+    }
+
+    #[test]
+    fn keeps_zero_width_non_joiner_inside_words() {
+        let filter = WordListFilter::new(["بی\u{200c}شرف".to_string()], WordListAction::Mask);
+
+        assert_eq!(
+            rewrite(&filter, "بی\u{200c}شرف!"),
+            FilterAction::Rewrite("******!".to_string())
+        );
+    }
+}
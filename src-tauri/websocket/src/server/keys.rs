@@ -0,0 +1,55 @@
+use crate::server::store::{spawn_writer, JsonStore};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{Mutex, Notify};
+
+/// Public keys users published for sealed prompts, the server never sees the
+/// secret halves. Anyone connecting under a name can replace its key, which is
+/// why clients warn when a key they knew changes.
+pub(crate) struct PublicKeys {
+    keys: Arc<Mutex<HashMap<String, String>>>,
+    // wakes the task that writes the keys to the store
+    changed: Arc<Notify>,
+}
+
+impl PublicKeys {
+    pub fn new(store: Option<JsonStore>) -> anyhow::Result<Self> {
+        let keys = match &store {
+            Some(store) => store.load()?,
+            None => HashMap::new(),
+        };
+
+        let keys = Arc::new(Mutex::new(keys));
+        let changed = Arc::new(Notify::new());
+
+        if let Some(store) = store {
+            spawn_writer(
+                store,
+                Arc::clone(&keys),
+                Arc::clone(&changed),
+                "public keys",
+            );
+        }
+
+        Ok(Self { keys, changed })
+    }
+
+    pub async fn get(&self, user_name: &str) -> Option<String> {
+        self.keys.lock().await.get(user_name).cloned()
+    }
+
+    /// returns true if the user had a different key before.
+    pub async fn publish(&self, user_name: &str, key: String) -> bool {
+        let mut keys = self.keys.lock().await;
+
+        let previous = keys.insert(user_name.to_string(), key.clone());
+
+        if previous.as_ref() == Some(&key) {
+            return false;
+        }
+
+        self.changed.notify_one();
+
+        previous.is_some()
+    }
+}
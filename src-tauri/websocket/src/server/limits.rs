@@ -0,0 +1,123 @@
+use crate::server::config::ConnectionsConfig;
+use axum::http::{header, HeaderMap, StatusCode};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// Why a connection was refused before it got to the username checks.
+#[derive(Debug)]
+pub(crate) enum ConnectionRefused {
+    OriginNotAllowed(String),
+    TooManyConnections,
+    TooManyFromAddress(IpAddr),
+}
+
+impl ConnectionRefused {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ConnectionRefused::OriginNotAllowed(_) => StatusCode::FORBIDDEN,
+            ConnectionRefused::TooManyConnections => StatusCode::SERVICE_UNAVAILABLE,
+            ConnectionRefused::TooManyFromAddress(_) => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+}
+
+impl fmt::Display for ConnectionRefused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionRefused::OriginNotAllowed(origin) => {
+                write!(f, "origin '{origin}' is not allowed")
+            }
+            ConnectionRefused::TooManyConnections => write!(f, "server is full"),
+            ConnectionRefused::TooManyFromAddress(ip) => {
+                write!(f, "too many connections from {ip}")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Keeps count of open connections and checks the upgrade requests of new ones.
+pub(crate) struct ConnectionLimits {
+    config: ConnectionsConfig,
+    counts: Mutex<Counts>,
+}
+
+impl ConnectionLimits {
+    pub fn new(config: ConnectionsConfig) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            counts: Mutex::new(Counts::default()),
+        })
+    }
+
+    // requests without an Origin header don't come from browsers, those are fine.
+    // browsers are only let in from the configured origins.
+    pub fn check_origin(&self, headers: &HeaderMap) -> Result<(), ConnectionRefused> {
+        let Some(origin) = headers.get(header::ORIGIN) else {
+            return Ok(());
+        };
+
+        let origin = origin.to_str().unwrap_or_default();
+        let allowed = self
+            .config
+            .allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin));
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(ConnectionRefused::OriginNotAllowed(origin.to_string()))
+        }
+    }
+
+    /// counts a new connection from `ip`, it is counted until the guard is dropped.
+    pub fn acquire(self: &Arc<Self>, ip: IpAddr) -> Result<ConnectionGuard, ConnectionRefused> {
+        let mut counts = self.counts.lock().unwrap();
+
+        if self.config.max_total.is_some_and(|max| counts.total >= max) {
+            return Err(ConnectionRefused::TooManyConnections);
+        }
+
+        let from_ip = counts.per_ip.get(&ip).copied().unwrap_or(0);
+        if self.config.max_per_ip.is_some_and(|max| from_ip >= max) {
+            return Err(ConnectionRefused::TooManyFromAddress(ip));
+        }
+
+        counts.total += 1;
+        *counts.per_ip.entry(ip).or_default() += 1;
+
+        Ok(ConnectionGuard {
+            limits: Arc::clone(self),
+            ip,
+        })
+    }
+}
+
+pub(crate) struct ConnectionGuard {
+    limits: Arc<ConnectionLimits>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut counts = self.limits.counts.lock().unwrap();
+
+        counts.total -= 1;
+
+        if let Some(count) = counts.per_ip.get_mut(&self.ip) {
+            *count -= 1;
+
+            if *count == 0 {
+                counts.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use tokio::sync::RwLock;
+
+/// The message of the day, sent to everyone who connects. It can be changed at
+/// runtime through the admin api.
+pub(crate) struct Motd {
+    template: RwLock<Option<String>>,
+}
+
+impl Motd {
+    pub fn new(template: Option<String>) -> Self {
+        Self {
+            template: RwLock::new(template),
+        }
+    }
+
+    pub async fn get(&self) -> Option<String> {
+        self.template.read().await.clone()
+    }
+
+    pub async fn set(&self, template: Option<String>) {
+        *self.template.write().await = template;
+    }
+
+    /// the motd for `user_name`, or None when there is nothing to send.
+    pub async fn render(&self, user_name: &str, online: usize) -> Option<String> {
+        let template = self.template.read().await;
+        let template = template.as_deref().filter(|t| !t.trim().is_empty())?;
+
+        Some(
+            template
+                .replace("{username}", user_name)
+                .replace("{online}", &online.to_string()),
+        )
+    }
+}
@@ -0,0 +1,100 @@
+use crate::server::config::{OnCallGroupConfig, RoutingStrategy};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+use tokio::sync::Mutex;
+
+struct OnCallGroup {
+    config: OnCallGroupConfig,
+    // where the next round robin turn starts
+    next: usize,
+    last_pinged: HashMap<String, Instant>,
+}
+
+impl OnCallGroup {
+    fn new(config: OnCallGroupConfig) -> Self {
+        Self {
+            config,
+            next: 0,
+            last_pinged: HashMap::new(),
+        }
+    }
+}
+
+/// Groups of users that take prompts in turns. They start out as the ones in
+/// the config, changes through the admin api last until the server restarts.
+pub(crate) struct OnCallGroups {
+    groups: Mutex<BTreeMap<String, OnCallGroup>>,
+}
+
+impl OnCallGroups {
+    pub fn new(configs: &[OnCallGroupConfig]) -> Self {
+        let groups = configs
+            .iter()
+            .map(|config| (config.name.clone(), OnCallGroup::new(config.clone())))
+            .collect();
+
+        Self {
+            groups: Mutex::new(groups),
+        }
+    }
+
+    pub async fn list(&self) -> Vec<OnCallGroupConfig> {
+        self.groups
+            .lock()
+            .await
+            .values()
+            .map(|group| group.config.clone())
+            .collect()
+    }
+
+    /// adds the group, or replaces the one with the same name and starts its turns over.
+    pub async fn set(&self, config: OnCallGroupConfig) {
+        self.groups
+            .lock()
+            .await
+            .insert(config.name.clone(), OnCallGroup::new(config));
+    }
+
+    /// returns false if there was no group with that name.
+    pub async fn remove(&self, name: &str) -> bool {
+        self.groups.lock().await.remove(name).is_some()
+    }
+
+    /// members of the group in the order they should be tried, None for unknown groups.
+    pub async fn candidates(&self, name: &str) -> Option<Vec<String>> {
+        let groups = self.groups.lock().await;
+        let group = groups.get(name)?;
+        let mut members = group.config.members.clone();
+
+        match group.config.strategy {
+            RoutingStrategy::Priority => {}
+            RoutingStrategy::RoundRobin => {
+                if !members.is_empty() {
+                    let start = group.next % members.len();
+                    members.rotate_left(start);
+                }
+            }
+            // members who never got one come first, sort_by_key is stable so
+            // ties keep the config order
+            RoutingStrategy::LeastRecentlyPinged => {
+                members.sort_by_key(|member| group.last_pinged.get(member).copied());
+            }
+        }
+
+        Some(members)
+    }
+
+    /// remembers that `member` got the group's latest prompt.
+    pub async fn pinged(&self, name: &str, member: &str) {
+        let mut groups = self.groups.lock().await;
+        let Some(group) = groups.get_mut(name) else {
+            return;
+        };
+
+        if let Some(index) = group.config.members.iter().position(|m| m == member) {
+            group.next = index + 1;
+        }
+
+        group.last_pinged.insert(member.to_string(), Instant::now());
+    }
+}
@@ -0,0 +1,133 @@
+use crate::message::{QuotaUsage, ReceiverQuota};
+use crate::server::config::QuotaConfig;
+use crate::server::store::{spawn_writer, JsonStore};
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{Mutex, Notify};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct QuotaState {
+    day: NaiveDate,
+    /// sender -> prompts sent today
+    sent: HashMap<String, u32>,
+    /// receiver -> sender -> prompts accepted today
+    received: HashMap<String, HashMap<String, u32>>,
+}
+
+/// Counts delivered prompts per UTC day and enforces the daily limits.
+pub(crate) struct Quotas {
+    state: Arc<Mutex<QuotaState>>,
+    // wakes the task that writes the counters to the store
+    changed: Arc<Notify>,
+    config: QuotaConfig,
+}
+
+impl Quotas {
+    pub fn new(store: Option<JsonStore>, config: QuotaConfig) -> anyhow::Result<Self> {
+        let state = match &store {
+            Some(store) => store.load()?,
+            None => QuotaState::default(),
+        };
+
+        let state = Arc::new(Mutex::new(state));
+        let changed = Arc::new(Notify::new());
+
+        if let Some(store) = store {
+            spawn_writer(store, Arc::clone(&state), Arc::clone(&changed), "quotas");
+        }
+
+        Ok(Self {
+            state,
+            changed,
+            config,
+        })
+    }
+
+    fn enabled(&self) -> bool {
+        self.config.max_sent_per_day.is_some()
+            || self.config.max_received_per_sender_per_day.is_some()
+    }
+
+    /// counts a prompt from `from` to `to`, or returns false if it would go over a limit.
+    pub async fn consume(&self, from: &str, to: &str) -> bool {
+        if !self.enabled() {
+            return true;
+        }
+
+        let mut state = self.state.lock().await;
+        Self::roll_over(&mut state);
+
+        let sent = state.sent.get(from).copied().unwrap_or(0);
+        let received = state
+            .received
+            .get(to)
+            .and_then(|senders| senders.get(from))
+            .copied()
+            .unwrap_or(0);
+
+        if self.config.max_sent_per_day.is_some_and(|max| sent >= max)
+            || self
+                .config
+                .max_received_per_sender_per_day
+                .is_some_and(|max| received >= max)
+        {
+            return false;
+        }
+
+        *state.sent.entry(from.to_string()).or_default() += 1;
+        *state
+            .received
+            .entry(to.to_string())
+            .or_default()
+            .entry(from.to_string())
+            .or_default() += 1;
+
+        self.changed.notify_one();
+
+        true
+    }
+
+    pub async fn usage(&self, user_name: &str, receiver: Option<&str>) -> QuotaUsage {
+        let mut state = self.state.lock().await;
+        Self::roll_over(&mut state);
+
+        let receiver = receiver.map(|receiver| ReceiverQuota {
+            name: receiver.to_string(),
+            sent: state
+                .received
+                .get(receiver)
+                .and_then(|senders| senders.get(user_name))
+                .copied()
+                .unwrap_or(0),
+            max_sent: self.config.max_received_per_sender_per_day,
+        });
+
+        QuotaUsage {
+            sent: state.sent.get(user_name).copied().unwrap_or(0),
+            max_sent: self.config.max_sent_per_day,
+            receiver,
+            resets_at: next_reset(state.day),
+        }
+    }
+
+    // starts the counters over once the day has changed
+    fn roll_over(state: &mut QuotaState) {
+        let today = Utc::now().date_naive();
+
+        if state.day != today {
+            *state = QuotaState {
+                day: today,
+                ..QuotaState::default()
+            };
+        }
+    }
+}
+
+// saves the counters off the async runtime. changes made while a save is running
+// are picked up by one more save, so busy days don't mean a write per prompt.
+
+fn next_reset(day: NaiveDate) -> DateTime<Utc> {
+    (day + Days::new(1)).and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
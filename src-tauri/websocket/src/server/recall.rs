@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+struct Sent {
+    at: Instant,
+    receivers: Vec<String>,
+}
+
+/// Remembers who got which keyed prompt for as long as it may be recalled.
+pub(crate) struct RecallLog {
+    sent: Mutex<HashMap<(String, String), Sent>>,
+    window: Duration,
+}
+
+impl RecallLog {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            sent: Mutex::new(HashMap::new()),
+            window,
+        }
+    }
+
+    pub async fn record(&self, from: &str, key: &str, to: &str) {
+        let mut sent = self.sent.lock().await;
+        sent.retain(|_, s| s.at.elapsed() < self.window);
+
+        sent.entry((from.to_string(), key.to_string()))
+            .or_insert_with(|| Sent {
+                at: Instant::now(),
+                receivers: Vec::new(),
+            })
+            .receivers
+            .push(to.to_string());
+    }
+
+    /// the receivers of the prompt, or None if it is unknown or too old to recall.
+    pub async fn take(&self, from: &str, key: &str) -> Option<Vec<String>> {
+        let sent = self
+            .sent
+            .lock()
+            .await
+            .remove(&(from.to_string(), key.to_string()))?;
+
+        (sent.at.elapsed() < self.window).then_some(sent.receivers)
+    }
+}
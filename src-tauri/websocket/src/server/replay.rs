@@ -0,0 +1,216 @@
+use crate::message::ChatMessage;
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+#[derive(Debug, Default)]
+struct ReplayBuffer {
+    last_seq: u64,
+    messages: VecDeque<ChatMessage>,
+    disconnected_at: Option<Instant>,
+}
+
+/// Remembers the prompts sent to every user, numbered per user, until the user's
+/// client acknowledges them, so a client that lost its connection can pick up
+/// where it left off. Prompts sent while a user is reconnecting are kept here too.
+pub(crate) struct ReplayBuffers {
+    buffers: Mutex<HashMap<String, ReplayBuffer>>,
+    capacity: usize,
+    resume_window: Duration,
+}
+
+impl ReplayBuffers {
+    pub fn new(capacity: usize, resume_window: Duration) -> Self {
+        Self {
+            buffers: Mutex::new(HashMap::new()),
+            capacity,
+            resume_window,
+        }
+    }
+
+    /// numbers the message for its receiver and keeps a copy for replaying.
+    pub async fn record(&self, msg: &mut ChatMessage) {
+        let mut buffers = self.buffers.lock().await;
+        let buffer = buffers.entry(msg.to.clone()).or_default();
+
+        buffer.last_seq += 1;
+        msg.seq = Some(buffer.last_seq);
+
+        if self.capacity == 0 {
+            return;
+        }
+
+        // the oldest of the least important prompts makes room
+        if buffer.messages.len() == self.capacity {
+            let evicted = buffer
+                .messages
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, m)| m.priority)
+                .map(|(index, _)| index);
+
+            if let Some(index) = evicted {
+                buffer.messages.remove(index);
+            }
+        }
+
+        buffer.messages.push_back(msg.clone());
+    }
+
+    /// whether the user went away recently enough that they might come back for their messages.
+    pub async fn is_resumable(&self, user_name: &str) -> bool {
+        let mut buffers = self.buffers.lock().await;
+        self.expire(&mut buffers);
+
+        buffers
+            .get(user_name)
+            .is_some_and(|b| b.disconnected_at.is_some())
+    }
+
+    /// forgets the user's prompt with this number, their client has it.
+    pub async fn acknowledge(&self, user_name: &str, seq: u64) {
+        if let Some(buffer) = self.buffers.lock().await.get_mut(user_name) {
+            buffer.messages.retain(|m| m.seq != Some(seq));
+        }
+    }
+
+    pub async fn disconnected(&self, user_name: &str) {
+        if let Some(buffer) = self.buffers.lock().await.get_mut(user_name) {
+            buffer.disconnected_at = Some(Instant::now());
+        }
+    }
+
+    /// Marks the user as connected again and returns the prompts their client
+    /// never acknowledged, the most important first. The ones among them that
+    /// expired in the meantime are dropped and returned separately, so their
+    /// senders can be told.
+    pub async fn resume(
+        &self,
+        user_name: &str,
+        last_seen: Option<u64>,
+    ) -> (Vec<ChatMessage>, Vec<ChatMessage>) {
+        let mut buffers = self.buffers.lock().await;
+        self.expire(&mut buffers);
+
+        let buffer = buffers.entry(user_name.to_string()).or_default();
+        buffer.disconnected_at = None;
+
+        let (expired, messages) = std::mem::take(&mut buffer.messages)
+            .into_iter()
+            .partition::<VecDeque<_>, _>(ChatMessage::is_expired);
+        buffer.messages = messages;
+
+        match last_seen {
+            // whatever is left never reached the client, even if a replay before
+            // this one was cut short after some of the later prompts
+            Some(last_seen) if last_seen <= buffer.last_seq => {
+                let mut replay = Vec::from(buffer.messages.clone());
+                replay.sort_by_key(|m| (Reverse(m.priority), m.seq));
+
+                (replay, expired.into())
+            }
+            // numbers from before a server restart mean nothing to us. numbering
+            // goes on after them, clients keep the highest number they saw.
+            Some(last_seen) => {
+                buffer.last_seq = last_seen;
+
+                (Vec::new(), Vec::new())
+            }
+            // a new session, clients that resume send their number even before
+            // their first prompt. nothing of an earlier session is replayed to it.
+            None => {
+                buffer.messages.clear();
+
+                (Vec::new(), Vec::new())
+            }
+        }
+    }
+
+    /// drops the sender's prompt with this key from the user's buffer. returns true
+    /// if the user is away, so the prompt never reached them.
+    pub async fn remove(&self, user_name: &str, from: &str, key: &str) -> bool {
+        let mut buffers = self.buffers.lock().await;
+        let Some(buffer) = buffers.get_mut(user_name) else {
+            return false;
+        };
+
+        let before = buffer.messages.len();
+        buffer
+            .messages
+            .retain(|m| !(m.from == from && m.key.as_deref() == Some(key)));
+
+        buffer.messages.len() != before && buffer.disconnected_at.is_some()
+    }
+
+    // forgets users that did not come back in time
+    fn expire(&self, buffers: &mut HashMap<String, ReplayBuffer>) {
+        buffers.retain(|_, b| {
+            b.disconnected_at
+                .is_none_or(|at| at.elapsed() < self.resume_window)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{MessageContent, Priority};
+
+    fn texts(messages: &[ChatMessage]) -> Vec<&str> {
+        messages
+            .iter()
+            .map(|m| match &m.content {
+                MessageContent::Prompt(text) => text.as_str(),
+                _ => "",
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn replays_what_was_not_acknowledged_most_important_first() {
+        let buffers = ReplayBuffers::new(10, Duration::from_secs(60));
+
+        for (text, priority) in [
+            ("a", Priority::Normal),
+            ("b", Priority::Low),
+            ("c", Priority::Urgent),
+            ("d", Priority::Normal),
+        ] {
+            let mut msg = ChatMessage::new("alice", "bob", MessageContent::Prompt(text.into()))
+                .with_priority(priority);
+            buffers.record(&mut msg).await;
+        }
+
+        buffers.acknowledge("bob", 1).await;
+        buffers.disconnected("bob").await;
+
+        let (replay, expired) = buffers.resume("bob", Some(1)).await;
+        assert_eq!(texts(&replay), ["c", "d", "b"]);
+        assert!(expired.is_empty());
+
+        // the connection dropped again right after "c", the highest number so far
+        buffers.acknowledge("bob", 3).await;
+        buffers.disconnected("bob").await;
+
+        let (replay, _) = buffers.resume("bob", Some(3)).await;
+        assert_eq!(texts(&replay), ["d", "b"]);
+    }
+
+    #[tokio::test]
+    async fn replays_nothing_to_a_new_session() {
+        let buffers = ReplayBuffers::new(10, Duration::from_secs(60));
+
+        let mut msg = ChatMessage::new("alice", "bob", MessageContent::Prompt("a".into()));
+        buffers.record(&mut msg).await;
+        buffers.disconnected("bob").await;
+
+        let (replay, _) = buffers.resume("bob", None).await;
+        assert!(replay.is_empty());
+
+        buffers.disconnected("bob").await;
+
+        let (replay, _) = buffers.resume("bob", Some(0)).await;
+        assert!(replay.is_empty());
+    }
+}
@@ -0,0 +1,186 @@
+use crate::message::{ChatMessage, MessageContent, ScheduledPrompt};
+use crate::server::store::{spawn_writer, JsonStore};
+use crate::server::{route_message, Group};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify};
+
+const SCHEDULER_TICK: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct SchedulerState {
+    next_id: u64,
+    prompts: Vec<ScheduledPrompt>,
+}
+
+/// Keeps prompts that should be delivered later. Prompts stay pending until
+/// they are due and their receiver is online.
+pub(crate) struct Scheduler {
+    state: Arc<Mutex<SchedulerState>>,
+    // wakes the task that writes the pending prompts to the store
+    changed: Arc<Notify>,
+    max_per_user: usize,
+}
+
+impl Scheduler {
+    pub fn new(store: Option<JsonStore>, max_per_user: usize) -> anyhow::Result<Self> {
+        let state = match &store {
+            Some(store) => store.load()?,
+            None => SchedulerState::default(),
+        };
+
+        let state = Arc::new(Mutex::new(state));
+        let changed = Arc::new(Notify::new());
+
+        if let Some(store) = store {
+            spawn_writer(
+                store,
+                Arc::clone(&state),
+                Arc::clone(&changed),
+                "scheduled messages",
+            );
+        }
+
+        Ok(Self {
+            state,
+            changed,
+            max_per_user,
+        })
+    }
+
+    pub async fn schedule(
+        &self,
+        from: &str,
+        to: &str,
+        text: String,
+        deliver_at: DateTime<Utc>,
+    ) -> Result<ScheduledPrompt, String> {
+        let mut state = self.state.lock().await;
+
+        let pending = state.prompts.iter().filter(|p| p.from == from).count();
+        if pending >= self.max_per_user {
+            return Err(format!(
+                "you can't have more than {} scheduled messages",
+                self.max_per_user
+            ));
+        }
+
+        state.next_id += 1;
+
+        let prompt = ScheduledPrompt {
+            id: state.next_id,
+            from: from.to_string(),
+            to: to.to_string(),
+            text,
+            deliver_at,
+        };
+
+        state.prompts.push(prompt.clone());
+        self.changed.notify_one();
+
+        Ok(prompt)
+    }
+
+    pub async fn list(&self, user_name: &str) -> Vec<ScheduledPrompt> {
+        self.state
+            .lock()
+            .await
+            .prompts
+            .iter()
+            .filter(|p| p.from == user_name)
+            .cloned()
+            .collect()
+    }
+
+    /// cancels one of the user's own scheduled prompts, returns false if there was none.
+    pub async fn cancel(&self, user_name: &str, id: u64) -> bool {
+        let mut state = self.state.lock().await;
+        let before = state.prompts.len();
+
+        state
+            .prompts
+            .retain(|p| !(p.id == id && p.from == user_name));
+
+        let removed = state.prompts.len() != before;
+        if removed {
+            self.changed.notify_one();
+        }
+
+        removed
+    }
+
+    async fn take_due(&self, is_online: impl Fn(&str) -> bool) -> Vec<ScheduledPrompt> {
+        let now = Utc::now();
+        let mut state = self.state.lock().await;
+
+        let (due, pending) = std::mem::take(&mut state.prompts)
+            .into_iter()
+            .partition::<Vec<_>, _>(|p| p.deliver_at <= now && is_online(&p.to));
+
+        state.prompts = pending;
+
+        if !due.is_empty() {
+            self.changed.notify_one();
+        }
+
+        due
+    }
+}
+
+/// when a prompt sent `delay` from now is due, None if that can't be represented.
+pub(crate) fn deliver_at_after(delay: Duration) -> Option<DateTime<Utc>> {
+    let delay = chrono::TimeDelta::from_std(delay).ok()?;
+
+    Utc::now().checked_add_signed(delay)
+}
+
+// delivers due prompts as if their sender had just sent them.
+pub(crate) fn spawn_scheduler(group_state: Arc<Group>) {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(SCHEDULER_TICK);
+
+        loop {
+            tick.tick().await;
+
+            let online_users = group_state
+                .user_sinks
+                .read()
+                .await
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>();
+
+            let due = group_state
+                .scheduler
+                .take_due(|user| online_users.iter().any(|u| u == user))
+                .await;
+
+            for prompt in due {
+                // errors go back to the sender if they are online, otherwise they are dropped
+                let sender_tx = group_state
+                    .user_sinks
+                    .read()
+                    .await
+                    .get(&prompt.from)
+                    .cloned();
+                let sender_tx = sender_tx.unwrap_or_else(|| tokio::sync::mpsc::channel(1).0);
+
+                let msg = ChatMessage::new(
+                    &prompt.from,
+                    &prompt.to,
+                    MessageContent::Prompt(prompt.text),
+                );
+
+                if let Err(e) = route_message(&group_state, &prompt.from, &sender_tx, msg).await {
+                    tracing::debug!(
+                        "scheduled message {} had no one to report to: {}",
+                        prompt.id,
+                        e
+                    );
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,172 @@
+use crate::message::ChatMessage;
+use crate::server::audit::AuditEvent;
+use crate::server::limits::ConnectionGuard;
+use crate::server::webhook::WebhookEvent;
+use crate::server::{
+    admit_connection, join_user, refuse_connection, reject_username, remove_user, resume_user,
+    route_message, send_motd, Group, ResumeQuery,
+};
+use axum::extract::{ConnectInfo, Path, Query, State};
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::{http, Json};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc::Receiver;
+
+// Fallback transport for networks that don't let websocket upgrades through.
+// Messages for the user are streamed as server sent events, starting with a
+// "session" event carrying the token the client has to put on its POSTs.
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct SessionQuery {
+    session: String,
+}
+
+// owns the user's inbox for as long as the event stream is open
+struct SseSession {
+    group_state: Arc<Group>,
+    user_name: String,
+    rx: Receiver<ChatMessage>,
+    // numbers of the prompts that went out with the replay
+    replayed: HashSet<u64>,
+    _connection: ConnectionGuard,
+}
+
+impl Drop for SseSession {
+    fn drop(&mut self) {
+        let group_state = Arc::clone(&self.group_state);
+        let user_name = std::mem::take(&mut self.user_name);
+
+        tokio::spawn(async move {
+            remove_user(&group_state, &user_name).await;
+        });
+    }
+}
+
+pub(crate) async fn sse_handler(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(user_name): Path<String>,
+    Query(resume): Query<ResumeQuery>,
+    headers: HeaderMap,
+    State(group_state): State<Arc<Group>>,
+) -> Response {
+    let connection = match admit_connection(&group_state, addr, &headers) {
+        Ok(connection) => connection,
+        Err(e) => return refuse_connection(&group_state, user_name, addr, e),
+    };
+
+    let (tx, rx) = match join_user(&group_state, &user_name).await {
+        Ok(channel) => channel,
+        Err(e) => return reject_username(&group_state, user_name, addr, e),
+    };
+
+    // right away, so the user leaves again even if this request is dropped early
+    let mut session = SseSession {
+        group_state: Arc::clone(&group_state),
+        user_name: user_name.clone(),
+        rx,
+        replayed: HashSet::new(),
+        _connection: connection,
+    };
+
+    let token = uuid::Uuid::new_v4().to_string();
+    group_state
+        .sse_sessions
+        .write()
+        .await
+        .insert(user_name.clone(), token.clone());
+
+    tracing::info!("user {user_name} connected over sse: {}", addr);
+
+    group_state.webhooks.notify(WebhookEvent::UserConnected {
+        user: user_name.clone(),
+    });
+
+    group_state.audit.record(AuditEvent::Connected {
+        user: user_name.clone(),
+        addr,
+    });
+
+    let replay = resume_user(&group_state, &user_name, resume.last_seq).await;
+
+    if resume.last_seq.is_none() {
+        send_motd(&group_state, &user_name, &tx).await;
+    }
+
+    session.replayed = replay.iter().filter_map(|m| m.seq).collect();
+
+    let session_event = Event::default().event("session").data(token);
+    let replayed = futures_util::stream::iter(replay).map(|msg| Ok(message_event(&msg)));
+    let messages = futures_util::stream::unfold(session, |mut session| async move {
+        let msg = loop {
+            let msg = session.rx.recv().await?;
+
+            // already went out with the replay
+            if !msg.seq.is_some_and(|seq| session.replayed.contains(&seq)) {
+                break msg;
+            }
+        };
+
+        Some((Ok::<_, Infallible>(message_event(&msg)), session))
+    });
+
+    let stream = futures_util::stream::once(async move { Ok(session_event) })
+        .chain(replayed)
+        .chain(messages);
+
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+fn message_event(msg: &ChatMessage) -> Event {
+    Event::default()
+        .event("message")
+        .json_data(msg)
+        .unwrap_or_else(|e| Event::default().comment(format!("failed to encode: {e}")))
+}
+
+pub(crate) async fn post_handler(
+    Path(user_name): Path<String>,
+    Query(query): Query<SessionQuery>,
+    headers: HeaderMap,
+    State(group_state): State<Arc<Group>>,
+    Json(chat_message): Json<ChatMessage>,
+) -> Response {
+    if let Err(e) = group_state.connections.check_origin(&headers) {
+        return (e.status(), e.to_string()).into_response();
+    }
+
+    let valid_session = group_state
+        .sse_sessions
+        .read()
+        .await
+        .get(&user_name)
+        .is_some_and(|token| *token == query.session);
+
+    let tx = group_state.user_sinks.read().await.get(&user_name).cloned();
+
+    let (true, Some(tx)) = (valid_session, tx) else {
+        return Response::builder()
+            .status(http::StatusCode::UNAUTHORIZED)
+            .body("unknown session".into())
+            .unwrap();
+    };
+
+    if let Err(e) = route_message(&group_state, &user_name, &tx, chat_message).await {
+        tracing::info!("sse client disconnected: {}", e);
+
+        return Response::builder()
+            .status(http::StatusCode::GONE)
+            .body("session closed".into())
+            .unwrap();
+    }
+
+    http::StatusCode::ACCEPTED.into_response()
+}
@@ -0,0 +1,73 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::{Mutex, Notify};
+
+/// A json file inside the server's data directory, used for state that has to
+/// survive restarts.
+#[derive(Debug, Clone)]
+pub(crate) struct JsonStore {
+    path: PathBuf,
+}
+
+impl JsonStore {
+    pub fn new(data_dir: &Path, file_name: &str) -> Self {
+        Self {
+            path: data_dir.join(file_name),
+        }
+    }
+
+    /// reads the stored value, falling back to the default when nothing was stored yet.
+    pub fn load<T: DeserializeOwned + Default>(&self) -> anyhow::Result<T> {
+        if !self.path.exists() {
+            return Ok(T::default());
+        }
+
+        let content = fs::read_to_string(&self.path)?;
+
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    // writes to a temporary file first so a crash never leaves a half written store behind
+    pub fn save<T: Serialize>(&self, value: &T) -> anyhow::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let tmp_path = self.path.with_extension("json.tmp");
+
+        fs::write(&tmp_path, serde_json::to_vec(value)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        Ok(())
+    }
+}
+
+/// writes `state` to the store every time `changed` is notified, off the async
+/// runtime. changes made while a write is running end up in the next one.
+pub(crate) fn spawn_writer<T>(
+    store: JsonStore,
+    state: Arc<Mutex<T>>,
+    changed: Arc<Notify>,
+    what: &'static str,
+) where
+    T: Serialize + Clone + Send + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            changed.notified().await;
+
+            let snapshot = state.lock().await.clone();
+            let store = store.clone();
+            let saved = tokio::task::spawn_blocking(move || store.save(&snapshot)).await;
+
+            match saved {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => tracing::error!("failed to persist {}: {:?}", what, e),
+                Err(e) => tracing::error!("{} writer panicked: {:?}", what, e),
+            }
+        }
+    });
+}
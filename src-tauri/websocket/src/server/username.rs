@@ -0,0 +1,145 @@
+use crate::message::UsernameError;
+use crate::server::config::UsernameConfig;
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::{skeleton, GeneralSecurityProfile, MixedScript};
+
+// zero width non-joiner is a regular part of persian names, e.g. "مهدی‌زاده"
+const ZWNJ: char = '\u{200c}';
+
+/// Checks usernames against the configured rules before they are let in.
+pub(crate) struct UsernameRules {
+    config: UsernameConfig,
+    // skeletons of reserved names, so "__SERVΕR__" with a greek epsilon is reserved too
+    reserved: Vec<String>,
+}
+
+impl UsernameRules {
+    pub fn new(config: UsernameConfig, extra_reserved: impl IntoIterator<Item = String>) -> Self {
+        let reserved = config
+            .reserved
+            .iter()
+            .cloned()
+            .chain(extra_reserved)
+            .map(|name| confusable_skeleton(&name))
+            .collect();
+
+        Self { config, reserved }
+    }
+
+    /// validates a username against the rules and the names of everyone online.
+    pub fn validate<'a>(
+        &self,
+        user_name: &str,
+        online_users: impl IntoIterator<Item = &'a String>,
+    ) -> Result<(), UsernameError> {
+        if user_name.nfc().ne(user_name.chars()) {
+            return Err(UsernameError::NotNormalized);
+        }
+
+        let length = user_name.chars().count();
+
+        if length < self.config.min_length {
+            return Err(UsernameError::TooShort(self.config.min_length));
+        }
+
+        if length > self.config.max_length {
+            return Err(UsernameError::TooLong(self.config.max_length));
+        }
+
+        if let Some(c) = user_name.chars().find(|c| !self.is_allowed_char(*c)) {
+            return Err(UsernameError::InvalidCharacter(c));
+        }
+
+        if self.config.reject_mixed_scripts && !user_name.is_single_script() {
+            return Err(UsernameError::MixedScripts);
+        }
+
+        let user_skeleton = confusable_skeleton(user_name);
+
+        if self.reserved.contains(&user_skeleton) {
+            return Err(UsernameError::Reserved);
+        }
+
+        for online_user in online_users {
+            if online_user == user_name {
+                return Err(UsernameError::AlreadyTaken);
+            }
+
+            if confusable_skeleton(online_user) == user_skeleton {
+                return Err(UsernameError::ConfusableWith(online_user.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_allowed_char(&self, c: char) -> bool {
+        if self.config.allowed_symbols.contains(c) {
+            return true;
+        }
+
+        if c == ZWNJ {
+            return self.config.allow_letters;
+        }
+
+        if c.is_alphabetic() {
+            return self.config.allow_letters && c.identifier_allowed();
+        }
+
+        if c.is_numeric() {
+            return self.config.allow_digits && c.identifier_allowed();
+        }
+
+        false
+    }
+}
+
+fn confusable_skeleton(name: &str) -> String {
+    skeleton(&name.to_lowercase()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> UsernameRules {
+        let config = UsernameConfig {
+            reserved: vec!["admin".to_string()],
+            ..UsernameConfig::default()
+        };
+
+        UsernameRules::new(config, ["__SERVER__".to_string()])
+    }
+
+    #[test]
+    fn validates_names() {
+        let online = ["alice".to_string(), "ace".to_string()];
+
+        let cases = [
+            ("bob", Ok(())),
+            ("bob.smith-2", Ok(())),
+            // persian names keep their zero width non-joiner
+            ("مهدی\u{200c}زاده", Ok(())),
+            ("b", Err(UsernameError::TooShort(2))),
+            ("bob smith", Err(UsernameError::InvalidCharacter(' '))),
+            // "é" as "e" and a combining acute accent
+            ("jose\u{301}", Err(UsernameError::NotNormalized)),
+            // latin "p" with a cyrillic "а"
+            ("pаypal", Err(UsernameError::MixedScripts)),
+            ("admin", Err(UsernameError::Reserved)),
+            ("ADMIN", Err(UsernameError::Reserved)),
+            ("__server__", Err(UsernameError::Reserved)),
+            ("alice", Err(UsernameError::AlreadyTaken)),
+            (
+                "Alice",
+                Err(UsernameError::ConfusableWith("alice".to_string())),
+            ),
+            // all cyrillic, looks just like "ace"
+            ("асе", Err(UsernameError::ConfusableWith("ace".to_string()))),
+        ];
+
+        for (name, expected) in cases {
+            assert_eq!(rules().validate(name, &online), expected, "{name}");
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use crate::server::config::{WebhookEndpoint, WebhooksConfig};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+/// header carrying the hex encoded HMAC-SHA256 of the body, keyed with the endpoint secret.
+pub const SIGNATURE_HEADER: &str = "X-Ferris-Signature";
+pub const EVENT_HEADER: &str = "X-Ferris-Event";
+
+// the longest wait between two attempts, however many retries are configured.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum WebhookEvent {
+    UserConnected {
+        user: String,
+    },
+    UserDisconnected {
+        user: String,
+    },
+    MessageUndeliverable {
+        from: String,
+        to: String,
+    },
+    MessageFiltered {
+        from: String,
+        to: String,
+        /// reason of the rejection, none when the message was only rewritten.
+        reason: Option<String>,
+    },
+}
+
+impl WebhookEvent {
+    fn kind(&self) -> &'static str {
+        match self {
+            WebhookEvent::UserConnected { .. } => "user_connected",
+            WebhookEvent::UserDisconnected { .. } => "user_disconnected",
+            WebhookEvent::MessageUndeliverable { .. } => "message_undeliverable",
+            WebhookEvent::MessageFiltered { .. } => "message_filtered",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct WebhookPayload<'a> {
+    timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    event: &'a WebhookEvent,
+}
+
+/// Posts server events to the configured endpoints. Deliveries happen in the
+/// background, failed ones are retried with exponential backoff unless the
+/// endpoint answered with a client error.
+pub(crate) struct Webhooks {
+    tx: Option<UnboundedSender<WebhookEvent>>,
+}
+
+impl Webhooks {
+    pub fn from_config(config: &WebhooksConfig) -> Self {
+        if config.endpoints.is_empty() {
+            return Self { tx: None };
+        }
+
+        let (tx, mut rx) = mpsc::unbounded_channel::<WebhookEvent>();
+        let config = Arc::new(config.clone());
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap_or_default();
+
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                let endpoints = config
+                    .endpoints
+                    .iter()
+                    .filter(|e| e.events.is_empty() || e.events.iter().any(|k| k == event.kind()));
+
+                for endpoint in endpoints {
+                    tokio::spawn(deliver(
+                        http.clone(),
+                        Arc::clone(&config),
+                        endpoint.clone(),
+                        event.clone(),
+                    ));
+                }
+            }
+        });
+
+        Self { tx: Some(tx) }
+    }
+
+    pub fn notify(&self, event: WebhookEvent) {
+        if let Some(tx) = &self.tx {
+            if tx.send(event).is_err() {
+                tracing::error!("webhook dispatcher is gone, dropping event");
+            }
+        }
+    }
+}
+
+async fn deliver(
+    http: reqwest::Client,
+    config: Arc<WebhooksConfig>,
+    endpoint: WebhookEndpoint,
+    event: WebhookEvent,
+) {
+    let payload = WebhookPayload {
+        timestamp: Utc::now(),
+        event: &event,
+    };
+
+    let body = match serde_json::to_vec(&payload) {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!("failed to encode webhook payload: {:?}", e);
+            return;
+        }
+    };
+
+    let mut backoff = Duration::from_millis(config.initial_backoff_ms).min(MAX_BACKOFF);
+
+    for attempt in 0..=config.max_retries {
+        let mut request = http
+            .post(&endpoint.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, event.kind())
+            .body(body.clone());
+
+        if let Some(secret) = &endpoint.secret {
+            request = request.header(SIGNATURE_HEADER, format!("sha256={}", sign(secret, &body)));
+        }
+
+        // only server errors and network failures are worth another attempt.
+        match request.send().await {
+            Ok(response) if response.status().is_server_error() => {
+                tracing::warn!(
+                    "webhook to {} failed (attempt {}): {}",
+                    endpoint.url,
+                    attempt + 1,
+                    response.status()
+                );
+            }
+            Ok(response) if !response.status().is_success() => {
+                tracing::error!(
+                    "webhook to {} was refused: {}",
+                    endpoint.url,
+                    response.status()
+                );
+                return;
+            }
+            Ok(_) => return,
+            Err(e) => {
+                tracing::warn!(
+                    "webhook to {} failed (attempt {}): {}",
+                    endpoint.url,
+                    attempt + 1,
+                    e
+                );
+            }
+        }
+
+        if attempt < config.max_retries {
+            tokio::time::sleep(backoff).await;
+            backoff = backoff.saturating_mul(2).min(MAX_BACKOFF);
+        }
+    }
+
+    tracing::error!("giving up on webhook {} to {}", event.kind(), endpoint.url);
+}
+
+/// hex encoded HMAC-SHA256 of the body, what receivers should compare the signature header to.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any size");
+    mac.update(body);
+
+    hex::encode(mac.finalize().into_bytes())
+}
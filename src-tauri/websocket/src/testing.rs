@@ -0,0 +1,151 @@
+//! Helpers for integration tests: a server running in-process on an ephemeral
+//! port and clients connected to it. Enabled with the `testing` feature.
+
+use crate::client::ChatHandle;
+use crate::message::ChatMessage;
+use crate::server::config::ServerConfig;
+use crate::server::ServerBuilder;
+use std::net::SocketAddr;
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::task::JoinHandle;
+
+/// how long the `expect_*` helpers wait before failing the test
+pub const EXPECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct TestServer {
+    addr: SocketAddr,
+    task: JoinHandle<anyhow::Result<()>>,
+}
+
+impl TestServer {
+    pub async fn start() -> Self {
+        Self::with_config(ServerConfig::default()).await
+    }
+
+    pub async fn with_config(config: ServerConfig) -> Self {
+        Self::with_builder(ServerBuilder::new("0").config(config)).await
+    }
+
+    /// starts the server the builder describes, for tests that need filters or bots.
+    pub async fn with_builder(builder: ServerBuilder) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let task = tokio::spawn(builder.serve_on(listener));
+
+        Self { addr, task }
+    }
+
+    /// address in the form the clients expect, e.g. "127.0.0.1:41234".
+    pub fn addr(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// connects a client, panicking if the server refuses it.
+    pub async fn client(&self, name: &str) -> TestClient {
+        let handle = self
+            .try_client(name)
+            .await
+            .unwrap_or_else(|e| panic!("{name} failed to connect: {e}"));
+
+        TestClient::new(handle)
+    }
+
+    pub async fn try_client(&self, name: &str) -> anyhow::Result<ChatHandle> {
+        ChatHandle::new(name.to_string(), self.addr()).await
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// A connected client with assertions on what it receives, in the order it
+/// received them. Derefs to the `ChatHandle` for sending.
+pub struct TestClient {
+    handle: ChatHandle,
+    receiver: UnboundedReceiver<ChatMessage>,
+}
+
+impl TestClient {
+    /// takes over the handle's queued receiver, so it sees everything the handle got.
+    pub fn new(mut handle: ChatHandle) -> Self {
+        let receiver = handle
+            .take_queued_receiver()
+            .expect("the handle's receiver was already taken");
+
+        Self { handle, receiver }
+    }
+
+    /// waits for the next message and fails the test unless it matches.
+    pub async fn expect_next(&mut self, matches: impl Fn(&ChatMessage) -> bool) -> ChatMessage {
+        let msg = self
+            .recv(EXPECT_TIMEOUT)
+            .await
+            .expect("timed out waiting for the next message");
+
+        assert!(matches(&msg), "next message did not match: {msg:?}");
+
+        msg
+    }
+
+    /// skips messages until one matches, failing the test if none comes in time.
+    pub async fn expect_message(&mut self, matches: impl Fn(&ChatMessage) -> bool) -> ChatMessage {
+        let deadline = tokio::time::Instant::now() + EXPECT_TIMEOUT;
+
+        loop {
+            let left = deadline.saturating_duration_since(tokio::time::Instant::now());
+
+            match self.recv(left).await {
+                Some(msg) if matches(&msg) => return msg,
+                Some(msg) => tracing::debug!("skipping message: {msg:?}"),
+                None => panic!("timed out waiting for a matching message"),
+            }
+        }
+    }
+
+    /// fails the test if a matching message comes in within `within`.
+    pub async fn expect_no_message(
+        &mut self,
+        within: Duration,
+        matches: impl Fn(&ChatMessage) -> bool,
+    ) {
+        let deadline = tokio::time::Instant::now() + within;
+
+        loop {
+            let left = deadline.saturating_duration_since(tokio::time::Instant::now());
+
+            match self.recv(left).await {
+                Some(msg) if matches(&msg) => panic!("got unexpected message: {msg:?}"),
+                Some(_) => {}
+                None => return,
+            }
+        }
+    }
+
+    // None on timeout or when the connection is gone
+    async fn recv(&mut self, timeout: Duration) -> Option<ChatMessage> {
+        tokio::time::timeout(timeout, self.receiver.recv())
+            .await
+            .ok()?
+    }
+}
+
+impl Deref for TestClient {
+    type Target = ChatHandle;
+
+    fn deref(&self) -> &Self::Target {
+        &self.handle
+    }
+}
+
+impl DerefMut for TestClient {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.handle
+    }
+}
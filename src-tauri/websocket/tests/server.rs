@@ -0,0 +1,1245 @@
+use axum::body::{Body, Bytes};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, Method, StatusCode, Uri};
+use axum::routing::{get, post};
+use axum::Router;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use websocket::client::ChatHandle;
+use websocket::e2e::Identity;
+use websocket::message::{
+    ChatError, ChatMessage, DeliveryStatus, MessageContent, PresenceStatus, Priority, ScheduleTime,
+    UsernameError,
+};
+use websocket::server::bot::EchoBot;
+use websocket::server::config::{
+    ApiConfig, ApiToken, OnCallGroupConfig, RoutingStrategy, ServerConfig, WebhookEndpoint,
+    WebhooksConfig,
+};
+use websocket::server::webhook::{sign, EVENT_HEADER, SIGNATURE_HEADER};
+use websocket::server::ServerBuilder;
+use websocket::testing::{TestClient, TestServer};
+
+#[tokio::test]
+async fn relays_prompts_between_users() {
+    let server = TestServer::start().await;
+    let mut alice = server.client("alice").await;
+    let mut bob = server.client("bob").await;
+
+    alice
+        .send_text("bob".to_string(), "hello bob".to_string())
+        .await
+        .unwrap();
+
+    let msg = bob
+        .expect_message(|m| matches!(m.content, MessageContent::Prompt(_)))
+        .await;
+
+    assert_eq!(msg.from, "alice");
+    assert!(matches!(&msg.content, MessageContent::Prompt(text) if text == "hello bob"));
+}
+
+#[tokio::test]
+async fn throttles_typing_indicators() {
+    let config = ServerConfig {
+        typing_throttle_ms: 500,
+        ..ServerConfig::default()
+    };
+
+    let server = TestServer::with_config(config).await;
+    let mut alice = server.client("alice").await;
+    let mut bob = server.client("bob").await;
+
+    alice.send_typing("bob".to_string()).await.unwrap();
+    alice.send_typing("bob".to_string()).await.unwrap();
+
+    let msg = bob
+        .expect_message(|m| matches!(m.content, MessageContent::Typing))
+        .await;
+    assert_eq!(msg.from, "alice");
+
+    bob.expect_no_message(Duration::from_millis(300), |m| {
+        matches!(m.content, MessageContent::Typing)
+    })
+    .await;
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    alice.send_typing("bob".to_string()).await.unwrap();
+
+    bob.expect_message(|m| matches!(m.content, MessageContent::Typing))
+        .await;
+}
+
+#[tokio::test]
+async fn reports_offline_receivers() {
+    let server = TestServer::start().await;
+    let mut alice = server.client("alice").await;
+
+    alice
+        .send_text("nobody".to_string(), "are you there?".to_string())
+        .await
+        .unwrap();
+
+    alice
+        .expect_message(|m| matches!(m.content, MessageContent::Error(ChatError::UserNotOnline)))
+        .await;
+}
+
+#[tokio::test]
+async fn refuses_duplicate_names() {
+    let server = TestServer::start().await;
+    let _alice = server.client("alice").await;
+
+    let err = server.try_client("alice").await.err().unwrap();
+
+    assert_eq!(
+        err.downcast_ref::<UsernameError>(),
+        Some(&UsernameError::AlreadyTaken)
+    );
+}
+
+#[tokio::test]
+async fn lists_online_users() {
+    let server = TestServer::start().await;
+    let mut alice = server.client("alice").await;
+    let _bob = server.client("bob").await;
+
+    alice.list_users().await.unwrap();
+
+    let msg = alice
+        .expect_message(|m| matches!(m.content, MessageContent::ListUsers(_)))
+        .await;
+
+    let MessageContent::ListUsers(users) = msg.content else {
+        unreachable!()
+    };
+    let mut names = users.into_iter().map(|u| u.name).collect::<Vec<_>>();
+    names.sort();
+
+    assert_eq!(names, ["alice", "bob"]);
+}
+
+#[tokio::test]
+async fn enforces_daily_quotas() {
+    let mut config = ServerConfig::default();
+    config.quotas.max_sent_per_day = Some(3);
+    config.quotas.max_received_per_sender_per_day = Some(2);
+
+    let server = TestServer::with_config(config).await;
+    let mut alice = server.client("alice").await;
+    let _bob = server.client("bob").await;
+    let _carol = server.client("carol").await;
+
+    for _ in 0..2 {
+        alice
+            .send_text("bob".to_string(), "hi".to_string())
+            .await
+            .unwrap();
+    }
+
+    // bob takes no more from alice today, carol still does
+    alice
+        .send_text("bob".to_string(), "hi".to_string())
+        .await
+        .unwrap();
+    alice
+        .expect_message(|m| matches!(m.content, MessageContent::Error(ChatError::QuotaExceeded)))
+        .await;
+
+    alice
+        .send_text("carol".to_string(), "hi".to_string())
+        .await
+        .unwrap();
+    alice.get_quota(Some("bob".to_string())).await.unwrap();
+
+    let msg = alice
+        .expect_message(|m| matches!(m.content, MessageContent::Quota(_)))
+        .await;
+    let MessageContent::Quota(usage) = msg.content else {
+        unreachable!()
+    };
+
+    assert_eq!((usage.sent, usage.max_sent), (3, Some(3)));
+    let receiver = usage.receiver.unwrap();
+    assert_eq!((receiver.sent, receiver.max_sent), (2, Some(2)));
+}
+
+#[tokio::test]
+async fn does_not_count_bot_replies_against_quotas() {
+    let mut config = ServerConfig::default();
+    config.quotas.max_sent_per_day = Some(1);
+
+    let server = TestServer::with_builder(
+        ServerBuilder::new("0")
+            .config(config)
+            .bot(EchoBot::new("echo".to_string())),
+    )
+    .await;
+    let mut alice = server.client("alice").await;
+    let mut bob = server.client("bob").await;
+
+    for client in [&mut alice, &mut bob] {
+        client
+            .send_text("echo".to_string(), "ping".to_string())
+            .await
+            .unwrap();
+
+        client
+            .expect_message(|m| {
+                m.from == "echo" && matches!(&m.content, MessageContent::Prompt(t) if t == "ping")
+            })
+            .await;
+    }
+}
+
+#[tokio::test]
+async fn refuses_to_start_with_invalid_bot_names() {
+    for name in ["__SERVER__", "e cho"] {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let served = ServerBuilder::new("0")
+            .bot(EchoBot::new(name.to_string()))
+            .serve_on(listener)
+            .await;
+
+        assert!(served.is_err(), "{name}");
+    }
+}
+
+#[tokio::test]
+async fn rejects_prompts_scheduled_too_far_ahead() {
+    let server = TestServer::start().await;
+    let mut alice = server.client("alice").await;
+
+    alice
+        .schedule_text(
+            "bob".to_string(),
+            "see you never".to_string(),
+            ScheduleTime::After(Duration::MAX),
+        )
+        .await
+        .unwrap();
+
+    alice
+        .expect_message(|m| matches!(m.content, MessageContent::Error(ChatError::Rejected(_))))
+        .await;
+
+    alice.list_users().await.unwrap();
+
+    alice
+        .expect_message(|m| matches!(m.content, MessageContent::ListUsers(_)))
+        .await;
+}
+
+#[tokio::test]
+async fn delivers_lists_cancels_and_keeps_scheduled_prompts() {
+    let data_dir = std::env::temp_dir().join(format!("ferris-test-{}", uuid::Uuid::new_v4()));
+    let config = ServerConfig {
+        data_dir: Some(data_dir.clone()),
+        ..ServerConfig::default()
+    };
+
+    let server = TestServer::with_config(config.clone()).await;
+    let mut alice = server.client("alice").await;
+    let mut bob = server.client("bob").await;
+
+    for (text, delay) in [("stand up", 1), ("go home", 3600)] {
+        alice
+            .schedule_text(
+                "bob".to_string(),
+                text.to_string(),
+                ScheduleTime::After(Duration::from_secs(delay)),
+            )
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(scheduled_texts(&mut alice).await, ["stand up"]);
+    assert_eq!(scheduled_texts(&mut alice).await, ["stand up", "go home"]);
+
+    let msg = bob
+        .expect_message(|m| matches!(m.content, MessageContent::Prompt(_)))
+        .await;
+    assert_eq!(msg.from, "alice");
+    assert!(matches!(&msg.content, MessageContent::Prompt(t) if t == "stand up"));
+
+    alice.list_scheduled().await.unwrap();
+    assert_eq!(scheduled_texts(&mut alice).await, ["go home"]);
+
+    // give the writer a moment, then start over from what it left in the data dir
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    drop((alice, bob, server));
+
+    let server = TestServer::with_config(config).await;
+    let mut alice = server.client("alice").await;
+
+    alice.list_scheduled().await.unwrap();
+    let msg = alice
+        .expect_message(|m| matches!(m.content, MessageContent::ScheduledPrompts(_)))
+        .await;
+    let MessageContent::ScheduledPrompts(prompts) = msg.content else {
+        unreachable!()
+    };
+    assert_eq!(prompts.len(), 1);
+    assert_eq!(prompts[0].text, "go home");
+
+    alice.cancel_scheduled(prompts[0].id).await.unwrap();
+    assert!(scheduled_texts(&mut alice).await.is_empty());
+
+    std::fs::remove_dir_all(data_dir).unwrap();
+}
+
+#[tokio::test]
+async fn frees_the_names_of_clients_that_stop_answering() {
+    let mut config = ServerConfig::default();
+    config.connections.ping_interval_secs = 1;
+    config.connections.idle_timeout_secs = 2;
+
+    let server = TestServer::with_config(config).await;
+    let mut alice = server.client("alice").await;
+
+    // never read from, so it doesn't answer pings, like a peer that vanished
+    let (_ghost, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws/ghost", server.addr()))
+        .await
+        .unwrap();
+
+    wait_until_online(&mut alice, "ghost").await;
+    assert!(server.try_client("ghost").await.is_err());
+
+    tokio::time::sleep(Duration::from_secs(4)).await;
+
+    // alice answered the pings all along
+    wait_until_online(&mut alice, "alice").await;
+    server.try_client("ghost").await.unwrap();
+}
+
+#[tokio::test]
+async fn falls_back_to_server_sent_events() {
+    let server = TestServer::start().await;
+    let proxy = proxy_without_websockets(server.addr()).await;
+
+    let mut alice = TestClient::new(ChatHandle::new("alice".to_string(), proxy).await.unwrap());
+    let mut bob = server.client("bob").await;
+
+    bob.send_text("alice".to_string(), "can you hear me?".to_string())
+        .await
+        .unwrap();
+
+    let msg = alice
+        .expect_message(|m| matches!(m.content, MessageContent::Prompt(_)))
+        .await;
+    assert!(matches!(&msg.content, MessageContent::Prompt(t) if t == "can you hear me?"));
+
+    alice
+        .send_text("bob".to_string(), "loud and clear".to_string())
+        .await
+        .unwrap();
+
+    let msg = bob
+        .expect_message(|m| matches!(m.content, MessageContent::Prompt(_)))
+        .await;
+    assert_eq!(msg.from, "alice");
+    assert!(matches!(&msg.content, MessageContent::Prompt(t) if t == "loud and clear"));
+}
+
+#[tokio::test]
+async fn limits_connections_per_address() {
+    let mut config = ServerConfig::default();
+    config.connections.max_per_ip = Some(1);
+
+    let server = TestServer::with_config(config).await;
+    let _alice = server.client("alice").await;
+
+    let err = server.try_client("bob").await.err().unwrap();
+
+    assert!(err.to_string().contains("too many connections"), "{err}");
+}
+
+#[tokio::test]
+async fn refuses_browsers_from_unknown_origins() {
+    let mut config = ServerConfig::default();
+    config.connections.allowed_origins = vec!["https://ferris.example".to_string()];
+
+    let server = TestServer::with_config(config).await;
+    let http = reqwest::Client::new();
+    let url = format!("http://{}/sse/alice", server.addr());
+
+    let refused = http
+        .get(&url)
+        .header("Origin", "https://evil.example")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(refused.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let allowed = http
+        .get(&url)
+        .header("Origin", "https://ferris.example")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(allowed.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn greets_new_users_with_the_motd() {
+    let config = ServerConfig {
+        motd: Some("welcome {username}, {online} online".to_string()),
+        api: ApiConfig {
+            tokens: vec![ApiToken {
+                name: "ops".to_string(),
+                token: "secret".to_string(),
+                admin: true,
+            }],
+        },
+        ..ServerConfig::default()
+    };
+
+    let server = TestServer::with_config(config).await;
+    let mut alice = server.client("alice").await;
+
+    alice
+        .expect_message(|m| {
+            m.from == "__SERVER__"
+                && matches!(&m.content, MessageContent::Prompt(t) if t == "welcome alice, 1 online")
+        })
+        .await;
+
+    let response = reqwest::Client::new()
+        .put(format!("http://{}/api/admin/motd", server.addr()))
+        .bearer_auth("secret")
+        .json(&serde_json::json!({ "motd": "rules: be nice" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let mut bob = server.client("bob").await;
+
+    bob.expect_message(
+        |m| matches!(&m.content, MessageContent::Prompt(t) if t == "rules: be nice"),
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn sends_announcements_added_through_the_admin_api() {
+    let config = ServerConfig {
+        api: ApiConfig {
+            tokens: vec![ApiToken {
+                name: "ops".to_string(),
+                token: "secret".to_string(),
+                admin: true,
+            }],
+        },
+        ..ServerConfig::default()
+    };
+
+    let server = TestServer::with_config(config).await;
+    let mut alice = server.client("alice").await;
+    let http = reqwest::Client::new();
+    let url = format!("http://{}/api/admin/announcements", server.addr());
+
+    let invalid = http
+        .post(&url)
+        .bearer_auth("secret")
+        .json(&serde_json::json!({
+            "schedule": "0 10 * * Mon-Fri",
+            "timezone": "Mars/Olympus_Mons",
+            "text": "stand-up time!",
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(invalid.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let created = http
+        .post(&url)
+        .bearer_auth("secret")
+        .json(&serde_json::json!({
+            "schedule": "* * * * * *",
+            "text": "drink water",
+            "to": ["alice"],
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(created.status(), reqwest::StatusCode::CREATED);
+
+    alice
+        .expect_message(|m| {
+            m.from == "__SERVER__"
+                && matches!(&m.content, MessageContent::Prompt(t) if t == "drink water")
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn reports_multicast_results_per_recipient() {
+    let server = TestServer::start().await;
+    let mut alice = server.client("alice").await;
+    let mut bob = server.client("bob").await;
+    let mut carol = server.client("carol").await;
+
+    carol.block_user("alice".to_string()).await.unwrap();
+    carol
+        .expect_message(|m| matches!(m.content, MessageContent::BlockedUsers(_)))
+        .await;
+
+    let id = alice
+        .multicast_text(
+            vec!["bob".to_string(), "carol".to_string(), "dave".to_string()],
+            "lunch?".to_string(),
+        )
+        .await
+        .unwrap();
+
+    let msg = alice
+        .expect_message(|m| matches!(m.content, MessageContent::DeliveryReport(_)))
+        .await;
+    assert_eq!(msg.key, Some(id));
+    let MessageContent::DeliveryReport(results) = msg.content else {
+        unreachable!()
+    };
+
+    assert_eq!(results["bob"], DeliveryStatus::Delivered);
+    // carol's block stays hidden from alice
+    assert_eq!(results["carol"], DeliveryStatus::Delivered);
+    assert_eq!(results["dave"], DeliveryStatus::Offline);
+
+    let msg = bob
+        .expect_message(|m| matches!(m.content, MessageContent::Prompt(_)))
+        .await;
+
+    assert_eq!((msg.from.as_str(), msg.to.as_str()), ("alice", "bob"));
+
+    carol
+        .expect_no_message(Duration::from_millis(200), |m| {
+            matches!(m.content, MessageContent::Prompt(_))
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn marks_prompts_from_muted_senders() {
+    let server = TestServer::start().await;
+    let mut alice = server.client("alice").await;
+    let mut bob = server.client("bob").await;
+
+    bob.mute_user("alice".to_string()).await.unwrap();
+    let msg = bob
+        .expect_message(|m| matches!(m.content, MessageContent::MutedUsers(_)))
+        .await;
+    assert!(matches!(&msg.content, MessageContent::MutedUsers(list) if list == &["alice"]));
+
+    alice.send_typing("bob".to_string()).await.unwrap();
+    alice
+        .send_text("bob".to_string(), "psst".to_string())
+        .await
+        .unwrap();
+
+    let msg = bob
+        .expect_message(|m| {
+            matches!(
+                m.content,
+                MessageContent::Prompt(_) | MessageContent::Typing
+            )
+        })
+        .await;
+    assert!(matches!(&msg.content, MessageContent::Prompt(t) if t == "psst"));
+    assert!(msg.muted);
+
+    bob.unmute_user("alice".to_string()).await.unwrap();
+    bob.expect_message(|m| matches!(m.content, MessageContent::MutedUsers(_)))
+        .await;
+
+    alice
+        .send_text("bob".to_string(), "hello?".to_string())
+        .await
+        .unwrap();
+
+    let msg = bob
+        .expect_message(|m| matches!(m.content, MessageContent::Prompt(_)))
+        .await;
+    assert!(!msg.muted);
+}
+
+#[tokio::test]
+async fn hides_blocks_from_api_senders() {
+    let config = ServerConfig {
+        api: ApiConfig {
+            tokens: vec![ApiToken {
+                name: "ci".to_string(),
+                token: "secret".to_string(),
+                admin: false,
+            }],
+        },
+        ..ServerConfig::default()
+    };
+
+    let server = TestServer::with_config(config).await;
+    let mut bob = server.client("bob").await;
+
+    bob.block_user("ci".to_string()).await.unwrap();
+    bob.expect_message(|m| matches!(m.content, MessageContent::BlockedUsers(_)))
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("http://{}/api/messages", server.addr()))
+        .bearer_auth("secret")
+        .json(&serde_json::json!({ "to": ["bob"], "text": "build is green" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["results"]["bob"], "delivered");
+
+    bob.expect_no_message(Duration::from_millis(200), |m| {
+        matches!(m.content, MessageContent::Prompt(_))
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn sends_api_prompts_once_per_receiver_up_to_the_limit() {
+    let config = ServerConfig {
+        api: ApiConfig {
+            tokens: vec![ApiToken {
+                name: "ci".to_string(),
+                token: "secret".to_string(),
+                admin: false,
+            }],
+        },
+        max_recipients: 2,
+        ..ServerConfig::default()
+    };
+
+    let server = TestServer::with_config(config).await;
+    let mut bob = server.client("bob").await;
+    let http = reqwest::Client::new();
+    let url = format!("http://{}/api/messages", server.addr());
+
+    let response = http
+        .post(&url)
+        .bearer_auth("secret")
+        .json(&serde_json::json!({ "to": ["bob", "bob"], "text": "build is green" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    bob.expect_message(|m| matches!(m.content, MessageContent::Prompt(_)))
+        .await;
+    bob.expect_no_message(Duration::from_millis(200), |m| {
+        matches!(m.content, MessageContent::Prompt(_))
+    })
+    .await;
+
+    let response = http
+        .post(&url)
+        .bearer_auth("secret")
+        .json(&serde_json::json!({ "to": ["bob", "carol", "dave"], "text": "build is red" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn routes_on_call_prompts_to_available_members_in_turns() {
+    let config = ServerConfig {
+        on_call: vec![OnCallGroupConfig {
+            name: "ops".to_string(),
+            members: vec!["alice".to_string(), "bob".to_string(), "carol".to_string()],
+            strategy: RoutingStrategy::RoundRobin,
+        }],
+        ..ServerConfig::default()
+    };
+
+    let server = TestServer::with_config(config).await;
+    let mut dave = server.client("dave").await;
+    let mut alice = server.client("alice").await;
+    let mut bob = server.client("bob").await;
+
+    // carol is offline, so alice and bob take turns
+    for expected in ["alice", "bob", "alice"] {
+        dave.send_on_call("ops".to_string(), "db is down".to_string())
+            .await
+            .unwrap();
+
+        let msg = dave
+            .expect_message(|m| matches!(m.content, MessageContent::OnCallRouted(..)))
+            .await;
+
+        assert!(
+            matches!(&msg.content, MessageContent::OnCallRouted(g, m) if g == "ops" && m == expected),
+            "{msg:?}"
+        );
+    }
+
+    // bob blocks dave and alice wants some quiet, that leaves nobody
+    bob.block_user("dave".to_string()).await.unwrap();
+    bob.expect_message(|m| matches!(m.content, MessageContent::BlockedUsers(_)))
+        .await;
+
+    alice
+        .set_presence(PresenceStatus::DoNotDisturb, None)
+        .await
+        .unwrap();
+    dave.expect_message(|m| matches!(m.content, MessageContent::PresenceChanged(_)))
+        .await;
+
+    dave.send_on_call("ops".to_string(), "still down".to_string())
+        .await
+        .unwrap();
+
+    let msg = dave
+        .expect_message(|m| matches!(m.content, MessageContent::Error(_)))
+        .await;
+
+    assert!(matches!(
+        &msg.content,
+        MessageContent::Error(ChatError::NoOneAvailable(g)) if g == "ops"
+    ));
+}
+
+#[tokio::test]
+async fn delivers_resent_messages_only_once() {
+    let server = TestServer::start().await;
+    let mut alice = server.client("alice").await;
+    let mut bob = server.client("bob").await;
+
+    let msg = ChatMessage::new("alice", "bob", MessageContent::Prompt("once".to_string()))
+        .with_key(Some("3b7e1d4c".to_string()));
+
+    alice
+        .retry_unacknowledged(vec![msg.clone(), msg])
+        .await
+        .unwrap();
+
+    bob.expect_message(|m| matches!(&m.content, MessageContent::Prompt(t) if t == "once"))
+        .await;
+    bob.expect_no_message(Duration::from_millis(300), |m| {
+        matches!(m.content, MessageContent::Prompt(_))
+    })
+    .await;
+
+    assert!(alice.unacknowledged().is_empty());
+}
+
+#[tokio::test]
+async fn relays_recalls_to_receivers() {
+    let server = TestServer::start().await;
+    let mut alice = server.client("alice").await;
+    let mut bob = server.client("bob").await;
+
+    let id = alice
+        .send_text("bob".to_string(), "oops, wrong person".to_string())
+        .await
+        .unwrap();
+
+    let msg = bob
+        .expect_message(|m| matches!(m.content, MessageContent::Prompt(_)))
+        .await;
+    assert_eq!(msg.key.as_ref(), Some(&id));
+
+    alice.recall(id.clone()).await.unwrap();
+
+    let msg = bob
+        .expect_message(|m| matches!(m.content, MessageContent::Recall(_)))
+        .await;
+    assert_eq!(msg.from, "alice");
+    assert!(matches!(&msg.content, MessageContent::Recall(key) if *key == id));
+
+    alice
+        .expect_message(|m| matches!(&m.content, MessageContent::Recall(key) if *key == id))
+        .await;
+
+    // a second recall finds nothing left to take back
+    alice.recall(id).await.unwrap();
+
+    alice
+        .expect_message(|m| matches!(m.content, MessageContent::Error(ChatError::Rejected(_))))
+        .await;
+}
+
+#[tokio::test]
+async fn replays_prompts_to_users_that_never_got_one_before() {
+    let server = TestServer::start().await;
+    let mut alice = server.client("alice").await;
+    let mut bob = server.try_client("bob").await.unwrap();
+
+    let last_seq = bob.last_seq();
+    bob.close().await.unwrap();
+
+    wait_until_offline(&mut alice, "bob").await;
+
+    alice
+        .send_text("bob".to_string(), "still there?".to_string())
+        .await
+        .unwrap();
+    // answered after the prompt was handled
+    alice.list_users().await.unwrap();
+    alice
+        .expect_message(|m| matches!(m.content, MessageContent::ListUsers(_)))
+        .await;
+
+    let mut bob = TestClient::new(
+        ChatHandle::resume("bob".to_string(), server.addr(), Some(last_seq))
+            .await
+            .unwrap(),
+    );
+
+    bob.expect_message(|m| matches!(&m.content, MessageContent::Prompt(t) if t == "still there?"))
+        .await;
+}
+
+#[tokio::test]
+async fn replays_unacknowledged_prompts_most_important_first() {
+    let config = ServerConfig {
+        urgent_senders: vec!["alice".to_string()],
+        ..ServerConfig::default()
+    };
+
+    let server = TestServer::with_config(config).await;
+    let mut alice = server.client("alice").await;
+    let mut bob = server.client("bob").await;
+
+    alice
+        .send_text("bob".to_string(), "seen".to_string())
+        .await
+        .unwrap();
+    bob.expect_message(|m| matches!(m.content, MessageContent::Prompt(_)))
+        .await;
+
+    let last_seq = bob.last_seq();
+    bob.close().await.unwrap();
+    drop(bob);
+
+    wait_until_offline(&mut alice, "bob").await;
+
+    for (text, priority) in [
+        ("low", Priority::Low),
+        ("normal", Priority::Normal),
+        ("urgent", Priority::Urgent),
+    ] {
+        alice
+            .send_text_with_priority("bob".to_string(), text.to_string(), priority)
+            .await
+            .unwrap();
+    }
+    alice.list_users().await.unwrap();
+    alice
+        .expect_message(|m| matches!(m.content, MessageContent::ListUsers(_)))
+        .await;
+
+    let mut bob = TestClient::new(
+        ChatHandle::resume("bob".to_string(), server.addr(), Some(last_seq))
+            .await
+            .unwrap(),
+    );
+
+    for expected in ["urgent", "normal", "low"] {
+        let msg = bob
+            .expect_message(|m| matches!(m.content, MessageContent::Prompt(_)))
+            .await;
+        assert!(matches!(&msg.content, MessageContent::Prompt(t) if t == expected));
+    }
+
+    bob.expect_no_message(Duration::from_millis(300), |m| {
+        matches!(m.content, MessageContent::Prompt(_))
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn expires_prompts_queued_for_too_long() {
+    let server = TestServer::start().await;
+    let mut alice = server.client("alice").await;
+    let mut bob = server.client("bob").await;
+
+    alice
+        .send_text("bob".to_string(), "first".to_string())
+        .await
+        .unwrap();
+    bob.expect_message(|m| matches!(m.content, MessageContent::Prompt(_)))
+        .await;
+
+    let last_seq = bob.last_seq();
+    bob.close().await.unwrap();
+    drop(bob);
+
+    wait_until_offline(&mut alice, "bob").await;
+
+    let id = alice
+        .send_expiring_text(
+            "bob".to_string(),
+            "meeting starts now".to_string(),
+            Duration::from_secs(1),
+        )
+        .await
+        .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    let mut bob = TestClient::new(
+        ChatHandle::resume("bob".to_string(), server.addr(), Some(last_seq))
+            .await
+            .unwrap(),
+    );
+
+    let report = alice
+        .expect_message(|m| matches!(m.content, MessageContent::DeliveryReport(_)))
+        .await;
+
+    assert_eq!(report.key, Some(id));
+    assert!(matches!(
+        &report.content,
+        MessageContent::DeliveryReport(results) if results.get("bob") == Some(&DeliveryStatus::Expired)
+    ));
+
+    bob.expect_no_message(Duration::from_millis(300), |m| {
+        matches!(m.content, MessageContent::Prompt(_))
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn lets_only_allowed_senders_through_do_not_disturb() {
+    let config = ServerConfig {
+        urgent_senders: vec!["alice".to_string()],
+        ..ServerConfig::default()
+    };
+
+    let server = TestServer::with_config(config).await;
+    let mut alice = server.client("alice").await;
+    let mut carol = server.client("carol").await;
+    let mut bob = server.client("bob").await;
+
+    bob.set_presence(PresenceStatus::DoNotDisturb, None)
+        .await
+        .unwrap();
+    alice
+        .expect_message(|m| matches!(m.content, MessageContent::PresenceChanged(_)))
+        .await;
+    carol
+        .expect_message(|m| matches!(m.content, MessageContent::PresenceChanged(_)))
+        .await;
+
+    carol
+        .send_text_with_priority("bob".to_string(), "lunch?".to_string(), Priority::Urgent)
+        .await
+        .unwrap();
+    carol
+        .expect_message(|m| {
+            matches!(
+                m.content,
+                MessageContent::Error(ChatError::UserDoNotDisturb)
+            )
+        })
+        .await;
+
+    alice
+        .send_text_with_priority(
+            "bob".to_string(),
+            "prod is down".to_string(),
+            Priority::Urgent,
+        )
+        .await
+        .unwrap();
+
+    let msg = bob
+        .expect_message(|m| matches!(m.content, MessageContent::Prompt(_)))
+        .await;
+
+    assert_eq!(msg.from, "alice");
+    assert_eq!(msg.priority, Priority::Urgent);
+}
+
+#[tokio::test]
+async fn pages_on_call_members_in_do_not_disturb_with_allowed_urgent_prompts() {
+    let config = ServerConfig {
+        api: ApiConfig {
+            tokens: vec![ApiToken {
+                name: "alerts".to_string(),
+                token: "secret".to_string(),
+                admin: false,
+            }],
+        },
+        on_call: vec![OnCallGroupConfig {
+            name: "ops".to_string(),
+            members: vec!["bob".to_string()],
+            strategy: RoutingStrategy::RoundRobin,
+        }],
+        urgent_senders: vec!["alerts".to_string()],
+        ..ServerConfig::default()
+    };
+
+    let server = TestServer::with_config(config).await;
+    let mut bob = server.client("bob").await;
+    let http = reqwest::Client::new();
+    let url = format!("http://{}/api/messages", server.addr());
+
+    bob.set_presence(PresenceStatus::DoNotDisturb, None)
+        .await
+        .unwrap();
+    // answered after the presence change was handled
+    bob.list_users().await.unwrap();
+    bob.expect_message(|m| matches!(m.content, MessageContent::ListUsers(_)))
+        .await;
+
+    let normal = http
+        .post(&url)
+        .bearer_auth("secret")
+        .json(&serde_json::json!({ "on_call": "ops", "text": "disk at 80%" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(normal.status(), reqwest::StatusCode::CONFLICT);
+
+    let urgent = http
+        .post(&url)
+        .bearer_auth("secret")
+        .json(&serde_json::json!({ "on_call": "ops", "text": "db is down", "priority": "urgent" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(urgent.status(), reqwest::StatusCode::OK);
+
+    bob.expect_message(|m| matches!(&m.content, MessageContent::Prompt(t) if t == "db is down"))
+        .await;
+}
+
+#[tokio::test]
+async fn relays_sealed_prompts_and_announces_key_changes() {
+    let server = TestServer::start().await;
+    let mut alice = server.client("alice").await;
+    let mut bob = server.client("bob").await;
+
+    let alice_identity = Identity::generate();
+    let bob_identity = Identity::generate();
+
+    bob.publish_key(bob_identity.public_key()).await.unwrap();
+    alice.get_public_key("bob".to_string()).await.unwrap();
+
+    // bob's key may not be in yet, ask until it is
+    let bob_key = loop {
+        let msg = alice
+            .expect_message(|m| matches!(m.content, MessageContent::PublicKey(..)))
+            .await;
+
+        if let MessageContent::PublicKey(_, Some(key)) = msg.content {
+            break key;
+        }
+
+        alice.get_public_key("bob".to_string()).await.unwrap();
+    };
+
+    assert_eq!(bob_key, bob_identity.public_key());
+
+    let sealed = alice_identity
+        .seal("alice", "bob", &bob_key, "only for bob")
+        .unwrap();
+    alice.send_sealed("bob".to_string(), sealed).await.unwrap();
+
+    let msg = bob
+        .expect_message(|m| matches!(m.content, MessageContent::SealedPrompt(_)))
+        .await;
+    let MessageContent::SealedPrompt(sealed) = &msg.content else {
+        unreachable!()
+    };
+
+    assert_eq!(sealed.sender_key, alice_identity.public_key());
+    assert_eq!(
+        bob_identity.open(&msg.from, "bob", sealed).unwrap(),
+        "only for bob"
+    );
+    // bound to its sender, the server can't pass it off as someone else's
+    assert!(bob_identity.open("carol", "bob", sealed).is_err());
+
+    let rotated = Identity::generate();
+    bob.publish_key(rotated.public_key()).await.unwrap();
+
+    let msg = alice
+        .expect_message(|m| matches!(m.content, MessageContent::PublicKey(..)))
+        .await;
+
+    assert!(matches!(
+        &msg.content,
+        MessageContent::PublicKey(user, Some(key)) if user == "bob" && *key == rotated.public_key()
+    ));
+}
+
+// answers webhook posts with `statuses` in turn, 200 once they run out, and
+// passes every request it got to the returned receiver.
+async fn webhook_stub(
+    statuses: Vec<StatusCode>,
+) -> (String, UnboundedReceiver<(HeaderMap, Bytes)>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let statuses = Arc::new(Mutex::new(VecDeque::from(statuses)));
+
+    let router = Router::new().route(
+        "/hook",
+        post(move |headers: HeaderMap, body: Bytes| async move {
+            let _ = tx.send((headers, body));
+
+            statuses
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or(StatusCode::OK)
+        }),
+    );
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, router).await });
+
+    (url, rx)
+}
+
+fn webhook_config(url: String) -> ServerConfig {
+    ServerConfig {
+        webhooks: WebhooksConfig {
+            endpoints: vec![WebhookEndpoint {
+                url,
+                secret: Some("hook-secret".to_string()),
+                events: vec!["user_connected".to_string()],
+            }],
+            max_retries: 3,
+            initial_backoff_ms: 10,
+        },
+        ..ServerConfig::default()
+    }
+}
+
+async fn next_webhook(rx: &mut UnboundedReceiver<(HeaderMap, Bytes)>) -> (HeaderMap, Bytes) {
+    tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("no webhook request")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn posts_signed_webhooks_and_retries_server_errors() {
+    let (url, mut requests) = webhook_stub(vec![StatusCode::SERVICE_UNAVAILABLE]).await;
+    let server = TestServer::with_config(webhook_config(url)).await;
+    let _alice = server.client("alice").await;
+
+    let (_, failed) = next_webhook(&mut requests).await;
+    let (headers, body) = next_webhook(&mut requests).await;
+
+    assert_eq!(failed, body);
+
+    let payload: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(payload["event"], "user_connected");
+    assert_eq!(payload["user"], "alice");
+
+    assert_eq!(headers[EVENT_HEADER], "user_connected");
+    assert_eq!(
+        headers[SIGNATURE_HEADER].to_str().unwrap(),
+        format!("sha256={}", sign("hook-secret", &body))
+    );
+
+    // delivered, so there is nothing left to retry
+    let retried = tokio::time::timeout(Duration::from_millis(300), requests.recv()).await;
+    assert!(retried.is_err());
+}
+
+#[tokio::test]
+async fn does_not_retry_webhooks_refused_by_the_endpoint() {
+    let (url, mut requests) = webhook_stub(vec![StatusCode::BAD_REQUEST]).await;
+    let server = TestServer::with_config(webhook_config(url)).await;
+    let _alice = server.client("alice").await;
+
+    next_webhook(&mut requests).await;
+
+    let retried = tokio::time::timeout(Duration::from_millis(300), requests.recv()).await;
+    assert!(retried.is_err());
+}
+
+// waits for the server to notice the user is gone
+// texts of the next list of scheduled prompts the client gets
+async fn scheduled_texts(client: &mut TestClient) -> Vec<String> {
+    let msg = client
+        .expect_message(|m| matches!(m.content, MessageContent::ScheduledPrompts(_)))
+        .await;
+    let MessageContent::ScheduledPrompts(prompts) = msg.content else {
+        unreachable!()
+    };
+
+    prompts.into_iter().map(|p| p.text).collect()
+}
+
+async fn wait_until_offline(client: &mut TestClient, user: &str) {
+    loop {
+        client.list_users().await.unwrap();
+        let msg = client
+            .expect_message(|m| matches!(m.content, MessageContent::ListUsers(_)))
+            .await;
+
+        if matches!(&msg.content, MessageContent::ListUsers(users) if users.iter().all(|u| u.name != user))
+        {
+            return;
+        }
+    }
+}
+
+async fn wait_until_online(client: &mut TestClient, user: &str) {
+    loop {
+        client.list_users().await.unwrap();
+        let msg = client
+            .expect_message(|m| matches!(m.content, MessageContent::ListUsers(_)))
+            .await;
+
+        if matches!(&msg.content, MessageContent::ListUsers(users) if users.iter().any(|u| u.name == user))
+        {
+            return;
+        }
+    }
+}
+
+// passes plain http requests on to the server but refuses websocket upgrades,
+// like the proxies the event stream fallback is for. returns its address.
+async fn proxy_without_websockets(server: String) -> String {
+    let http = reqwest::Client::new();
+
+    let router = Router::new()
+        .route(
+            "/ws/*user",
+            get(|| async { (StatusCode::BAD_GATEWAY, "no websockets here") }),
+        )
+        .fallback(move |method: Method, uri: Uri, body: Bytes| async move {
+            let response = http
+                .request(method, format!("http://{server}{uri}"))
+                .header(CONTENT_TYPE, "application/json")
+                .body(body)
+                .send()
+                .await
+                .unwrap();
+
+            (
+                response.status(),
+                Body::from_stream(response.bytes_stream()),
+            )
+        });
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move { axum::serve(listener, router).await });
+
+    addr
+}
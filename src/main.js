@@ -9,6 +9,27 @@ function loadText(textDescription) {
     textElement.innerText = textDescription;
 }
 
+let hideTimeout;
+
+// short two tone chime for urgent prompts, so no sound file has to be shipped
+function beep() {
+    const context = new AudioContext();
+
+    [880, 660].forEach((frequency, i) => {
+        const oscillator = context.createOscillator();
+        const gain = context.createGain();
+        const start = context.currentTime + i * 0.2;
+
+        oscillator.frequency.value = frequency;
+        gain.gain.setValueAtTime(0.2, start);
+        gain.gain.exponentialRampToValueAtTime(0.001, start + 0.18);
+
+        oscillator.connect(gain).connect(context.destination);
+        oscillator.start(start);
+        oscillator.stop(start + 0.18);
+    });
+}
+
 function loadImage(imageUrl) {
     // Get the image element
     let imgElement = document.getElementById('main-img');
@@ -16,6 +37,33 @@ function loadImage(imageUrl) {
     imgElement.src = imageUrl;
 }
 
+// pictures a sender can pick for their prompt, anything else gets the default
+const characters = {
+    "banana": "assets/banana.png",
+    "hamster": "assets/hamster.jpg",
+    "kyle": "assets/kyle.png",
+};
+
+let typingTimeout = null;
+
+function showTyping(user) {
+    let typingElement = document.getElementById('typing');
+    typingElement.innerText = user + " is typing…";
+
+    // indicators are sent every few seconds while typing, hide it once they stop
+    clearTimeout(typingTimeout);
+    typingTimeout = setTimeout(() => {
+        typingElement.innerText = "";
+    }, 4000);
+}
+
+// the users picked in the dropdown, holding ctrl picks more than one
+function selectedReceivers() {
+    let dropdown = document.getElementById('dropdown');
+
+    return Array.from(dropdown.selectedOptions).map((option) => option.value);
+}
+
 function toggle_view(showChat) {
     var prompt = document.getElementById('prompt');
     var chat = document.getElementById('chat');
@@ -34,12 +82,35 @@ function toggle_view(showChat) {
 window.addEventListener("DOMContentLoaded", () => {
     listen('chat_message', (event) => {
         // appWindow.show();
-        loadText(event.payload);
+        loadText(event.payload.text);
 
-        setTimeout(() => {
+        if (event.payload.sound) {
+            beep();
+        }
+
+        // a newer prompt gets its own full duration
+        clearTimeout(hideTimeout);
+        hideTimeout = setTimeout(() => {
             appWindow.hide();
             appWindow.setSkipTaskbar(true);
-        }, 5000);
+        }, event.payload.duration_ms);
+    });
+
+    // prompts from muted senders are there for whenever the window is opened next
+    listen('muted_message', (event) => {
+        loadText(event.payload);
+    });
+
+    listen('recall', (event) => {
+        loadText("");
+    });
+
+    listen('character', (event) => {
+        loadImage(characters[event.payload] || characters["banana"]);
+    });
+
+    listen('typing', (event) => {
+        showTyping(event.payload);
     });
 
     listen('send', (event) => {
@@ -49,7 +120,7 @@ window.addEventListener("DOMContentLoaded", () => {
     listen('online_users', (event) => {
         console.log("received");
         var dropdown = document.getElementById('dropdown');
-        var selected_option = document.getElementById('dropdown').value;
+        var selected_options = selectedReceivers();
 
         dropdown.innerHTML = "";
 
@@ -59,10 +130,7 @@ window.addEventListener("DOMContentLoaded", () => {
             var option = document.createElement('option');
             option.text = item;
             option.value = item;
-
-            if (item === selected_option) {
-                dropdown.value = item
-            }
+            option.selected = selected_options.includes(item);
 
             dropdown.add(option);
         });
@@ -80,10 +148,26 @@ window.onload = function () {
     let totalHeight = thoughtDiv.offsetHeight + marginTop + marginBottom;
     document.querySelector('.container').style.paddingTop = totalHeight + 'px';
 
+    let lastTypingSent = 0;
+    document.getElementById('textbox').oninput = function () {
+        let now = Date.now();
+
+        // the server throttles these anyway, no need to send one per key stroke
+        if (now - lastTypingSent > 1000) {
+            lastTypingSent = now;
+            selectedReceivers().forEach((receiver) => {
+                invoke('typing', { "receiver": receiver });
+            });
+        }
+    };
+
     document.getElementById('submit').onclick = function () {
-        var dropdown = document.getElementById('dropdown');
         var textbox = document.getElementById('textbox');
-        invoke('send_message', { "receiver": dropdown.value, "text": textbox.value });
+        if (selectedReceivers().length === 0) {
+            return;
+        }
+
+        invoke('send_message', { "receivers": selectedReceivers(), "text": textbox.value });
         toggle_view(false);
 
         appWindow.hide();